@@ -0,0 +1,96 @@
+use burn::{config::Config, constant, tensor::{activation::{gelu, relu, sigmoid}, backend::Backend, Tensor}};
+
+/// Nonlinearity after every hidden layer of a network.
+#[derive(Config, Debug)]
+pub enum Activation {
+    /// `x` for positive inputs, `negative_slope * x` for negative ones.
+    LeakyRelu { negative_slope: f64 },
+    Relu,
+    /// Gaussian error linear unit, `x * Phi(x)`.
+    Gelu,
+    /// Sigmoid linear unit, `x * sigmoid(x)`.
+    Silu,
+    /// `x` for positive inputs, `alpha * (exp(x) - 1)` for negative ones.
+    Elu { alpha: f64 },
+}
+
+// Stored in the networks as a constant, so it takes no space in the records and comes from the config on load.
+constant!(Activation);
+
+impl Activation {
+    /// The DCGAN default.
+    pub fn leaky_relu() -> Self {
+        Activation::LeakyRelu { negative_slope: 0.2 }
+    }
+
+    pub fn forward<B: Backend, const D: usize>(&self, x: Tensor<B, D>) -> Tensor<B, D> {
+        match self {
+            // `mask_where` instead of adding both clamped halves, which would double the gradient at 0.
+            Activation::LeakyRelu { negative_slope } => x.clone().mask_where(x.clone().lower_elem(0.0), x.mul_scalar(*negative_slope)),
+            Activation::Relu => relu(x),
+            Activation::Gelu => gelu(x),
+            Activation::Silu => x.clone() * sigmoid(x),
+            Activation::Elu { alpha } => x.clone().mask_where(x.clone().lower_elem(0.0), x.clamp_max(0.0).exp().sub_scalar(1.0).mul_scalar(*alpha)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{backend::{Autodiff, NdArray}, tensor::Data};
+
+    use super::*;
+
+    type TestBackend = NdArray<f32>;
+
+    const INPUTS: [f32; 5] = [-2.0, -0.5, 0.0, 0.5, 2.0];
+
+    fn assert_forward(activation: Activation, expected: [f32; 5]) {
+        let output = activation.forward(Tensor::<TestBackend, 1>::from_floats(INPUTS));
+        output.into_data().assert_approx_eq(&Data::from(expected), 4);
+    }
+
+    #[test]
+    fn leaky_relu_scales_negative_inputs_only() {
+        assert_forward(Activation::leaky_relu(), [-0.4, -0.1, 0.0, 0.5, 2.0]);
+        assert_forward(Activation::LeakyRelu { negative_slope: 0.01 }, [-0.02, -0.005, 0.0, 0.5, 2.0]);
+    }
+
+    #[test]
+    fn relu_zeroes_negative_inputs() {
+        assert_forward(Activation::Relu, [0.0, 0.0, 0.0, 0.5, 2.0]);
+    }
+
+    #[test]
+    fn gelu_matches_reference_values() {
+        assert_forward(Activation::Gelu, [-0.0455, -0.1543, 0.0, 0.3457, 1.9545]);
+    }
+
+    #[test]
+    fn silu_matches_reference_values() {
+        assert_forward(Activation::Silu, [-0.2384, -0.1888, 0.0, 0.3112, 1.7616]);
+    }
+
+    #[test]
+    fn elu_saturates_at_minus_alpha() {
+        assert_forward(Activation::Elu { alpha: 1.0 }, [-0.8647, -0.3935, 0.0, 0.5, 2.0]);
+        assert_forward(Activation::Elu { alpha: 0.5 }, [-0.4323, -0.1967, 0.0, 0.5, 2.0]);
+    }
+
+    fn assert_gradient(activation: Activation, expected: [f32; 5]) {
+        let input = Tensor::<Autodiff<TestBackend>, 1>::from_floats(INPUTS).require_grad();
+        let grads = activation.forward(input.clone()).sum().backward();
+        let gradient = input.grad(&grads).expect("Input should have a gradient");
+        gradient.into_data().assert_approx_eq(&Data::from(expected), 4);
+    }
+
+    #[test]
+    fn leaky_relu_gradient_is_slope_on_negative_side() {
+        assert_gradient(Activation::leaky_relu(), [0.2, 0.2, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn elu_gradient_is_continuous() {
+        assert_gradient(Activation::Elu { alpha: 1.0 }, [0.1353, 0.6065, 1.0, 1.0, 1.0]);
+    }
+}
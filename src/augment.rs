@@ -0,0 +1,220 @@
+use burn::{config::Config, tensor::{backend::Backend, Distribution, ElementConversion, Int, Tensor}};
+
+/// Largest brightness shift, as DiffAugment on the [-1, 1] range of the default `Normalization`.
+const BRIGHTNESS: f64 = 0.5;
+/// Largest translation, as a fraction of the image size.
+const TRANSLATION_RATIO: f64 = 0.125;
+/// Side of the cutout square, as a fraction of the image size.
+const CUTOUT_RATIO: f64 = 0.5;
+
+/// Differentiable augmentations of everything the discriminator sees, see https://arxiv.org/abs/2006.10738
+///
+/// Real and generated images go through the same augmentations, so the discriminator can not tell
+/// them apart by the augmentations and the generator is not pushed to produce augmented images.
+#[derive(Config, Debug)]
+pub struct AugmentConfig {
+    /// Random shifts by up to 1/8 of the image size, filled with zeros.
+    #[config(default = true)]
+    pub translation: bool,
+    /// A zeroed square of half the image size at a random position.
+    #[config(default = true)]
+    pub cutout: bool,
+    /// Random brightness, saturation and contrast.
+    #[config(default = true)]
+    pub color: bool,
+    /// Random horizontal flips.
+    #[config(default = false)]
+    pub flip: bool,
+    /// Probability each augmentation is applied to an image, the starting value when `ada` is set.
+    #[config(default = 1.0)]
+    pub probability: f64,
+    /// Tunes `probability` during training, `None` keeps it fixed.
+    pub ada: Option<AdaConfig>,
+}
+
+/// Adaptive augmentation as in StyleGAN2-ADA, see https://arxiv.org/abs/2006.06676
+///
+/// Overfitting is estimated by `E[sign(D(x))]` on the real images of each step: a discriminator that
+/// is sure about every training image gets stronger augmentation, an unsure one weaker.
+#[derive(Config, Debug)]
+pub struct AdaConfig {
+    /// Value of the overfitting heuristic the probability is steered towards.
+    #[config(default = 0.6)]
+    pub target: f64,
+    /// Number of images after which the probability can have moved from 0 to 1.
+    #[config(default = 100000)]
+    pub speed_images: usize,
+}
+
+impl AugmentConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.probability) {
+            return Err(format!("augmentation probability is {}, expected a value in [0, 1]", self.probability));
+        }
+        if let Some(ada) = &self.ada {
+            if !(0.0..=1.0).contains(&ada.target) {
+                return Err(format!("ada target is {}, expected a value in [0, 1]", ada.target));
+            }
+            if ada.speed_images == 0 {
+                return Err("ada speed_images has to be at least 1".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Augments every image of `images` `[batch, channels, height, width]`, each augmentation with `probability`.
+    pub fn augment<B: Backend>(&self, images: Tensor<B, 4>, probability: f64) -> Tensor<B, 4> {
+        let mut images = images;
+        if self.flip {
+            images = maybe(images, probability, flip);
+        }
+        if self.color {
+            images = maybe(images, probability, brightness);
+            images = maybe(images, probability, saturation);
+            images = maybe(images, probability, contrast);
+        }
+        if self.translation {
+            images = maybe(images, probability, translate);
+        }
+        if self.cutout {
+            images = maybe(images, probability, cutout);
+        }
+        images
+    }
+}
+
+impl AdaConfig {
+    /// Moves `probability` one step towards the target after a batch with discriminator logits `real_logits`.
+    pub fn adjust<B: Backend>(&self, probability: f64, real_logits: Tensor<B, 1>) -> f64 {
+        let [batch_size] = real_logits.dims();
+        let overfitting = real_logits.greater_elem(0.0).float().mean().into_scalar().elem::<f64>() * 2.0 - 1.0;
+        let step = batch_size as f64 / self.speed_images as f64;
+        (probability + (overfitting - self.target).signum() * step).clamp(0.0, 1.0)
+    }
+}
+
+/// Per-image random values `[batch, 1, 1, 1]` on the device of `images`.
+fn per_image<B: Backend>(images: &Tensor<B, 4>, distribution: Distribution) -> Tensor<B, 4> {
+    let [batch_size, _, _, _] = images.dims();
+    Tensor::random([batch_size, 1, 1, 1], distribution).to_device(&images.device())
+}
+
+/// Applies `augmentation` to each image with `probability`.
+fn maybe<B: Backend>(images: Tensor<B, 4>, probability: f64, augmentation: fn(Tensor<B, 4>) -> Tensor<B, 4>) -> Tensor<B, 4> {
+    if probability <= 0.0 {
+        return images;
+    }
+    if probability >= 1.0 {
+        return augmentation(images);
+    }
+    let mask = per_image(&images, Distribution::Bernoulli(probability));
+    let augmented = augmentation(images.clone());
+    images.clone() + (augmented - images) * mask
+}
+
+fn flip<B: Backend>(images: Tensor<B, 4>) -> Tensor<B, 4> {
+    let [_, _, _, width] = images.dims();
+    let mirrored = Tensor::<B, 1, Int>::arange_device(0..width, &images.device()).neg().add_scalar(width as i64 - 1);
+    images.select(3, mirrored)
+}
+
+fn brightness<B: Backend>(images: Tensor<B, 4>) -> Tensor<B, 4> {
+    let shift = per_image(&images, Distribution::Uniform(-BRIGHTNESS, BRIGHTNESS));
+    images + shift
+}
+
+fn saturation<B: Backend>(images: Tensor<B, 4>) -> Tensor<B, 4> {
+    let factor = per_image(&images, Distribution::Uniform(0.0, 2.0));
+    let gray = images.clone().mean_dim(1);
+    (images - gray.clone()) * factor + gray
+}
+
+fn contrast<B: Backend>(images: Tensor<B, 4>) -> Tensor<B, 4> {
+    let factor = per_image(&images, Distribution::Uniform(0.5, 1.5));
+    let mean = images.clone().mean_dim(1).mean_dim(2).mean_dim(3);
+    (images - mean.clone()) * factor + mean
+}
+
+/// Random integer offsets in `[0, max]`, `[batch, 1, 1, 1]`.
+fn offsets<B: Backend>(images: &Tensor<B, 4>, max: usize) -> Tensor<B, 4, Int> {
+    per_image(images, Distribution::Uniform(0.0, (max + 1) as f64)).int().clamp_max(max as i64)
+}
+
+fn translate<B: Backend>(images: Tensor<B, 4>) -> Tensor<B, 4> {
+    let [batch_size, channels, height, width] = images.dims();
+    let device = images.device();
+    let shift = (height.max(width) as f64 * TRANSLATION_RATIO).round() as usize;
+
+    // Pad by `shift` zeros on every side, then pick the window at a random offset into the padding.
+    let rows_padding = Tensor::zeros_device([batch_size, channels, shift, width], &device);
+    let padded = Tensor::cat(vec![rows_padding.clone(), images.clone(), rows_padding], 2);
+    let columns_padding = Tensor::zeros_device([batch_size, channels, height + 2 * shift, shift], &device);
+    let padded = Tensor::cat(vec![columns_padding.clone(), padded, columns_padding], 3);
+
+    let rows = Tensor::<B, 1, Int>::arange_device(0..height, &device).reshape([1, 1, height, 1]) + offsets(&images, 2 * shift);
+    let rows = rows.repeat(1, channels).repeat(3, width + 2 * shift);
+    let columns = Tensor::<B, 1, Int>::arange_device(0..width, &device).reshape([1, 1, 1, width]) + offsets(&images, 2 * shift);
+    let columns = columns.repeat(1, channels).repeat(2, height);
+    padded.gather(2, rows).gather(3, columns)
+}
+
+fn cutout<B: Backend>(images: Tensor<B, 4>) -> Tensor<B, 4> {
+    let [_, _, height, width] = images.dims();
+    let device = images.device();
+    let half_size = (height.max(width) as f64 * CUTOUT_RATIO) / 2.0;
+
+    // Pixel centers within half the cutout size of a random center, per axis.
+    let rows = Tensor::<B, 1, Int>::arange_device(0..height, &device).float().add_scalar(0.5).reshape([1, 1, height, 1]);
+    let inside_rows = (rows - per_image(&images, Distribution::Uniform(0.0, height as f64))).abs().lower_elem(half_size).float();
+    let columns = Tensor::<B, 1, Int>::arange_device(0..width, &device).float().add_scalar(0.5).reshape([1, 1, 1, width]);
+    let inside_columns = (columns - per_image(&images, Distribution::Uniform(0.0, width as f64))).abs().lower_elem(half_size).float();
+    images * (inside_rows * inside_columns).neg().add_scalar(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::backend::NdArray;
+
+    use super::*;
+
+    type TestBackend = NdArray<f32>;
+
+    fn ada() -> AdaConfig {
+        AdaConfig::new().with_speed_images(40)
+    }
+
+    fn logits(values: [f32; 4]) -> Tensor<TestBackend, 1> {
+        Tensor::from_floats(values)
+    }
+
+    #[test]
+    fn adjust_raises_probability_when_overfitting() {
+        let probability = ada().adjust(0.5, logits([1.0, 2.0, 0.5, 3.0]));
+        assert!((probability - 0.6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn adjust_lowers_probability_below_target() {
+        let probability = ada().adjust(0.5, logits([1.0, -2.0, 0.5, -3.0]));
+        assert!((probability - 0.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn adjust_keeps_probability_in_unit_interval() {
+        assert_eq!(ada().adjust(0.95, logits([1.0; 4])), 1.0);
+        assert_eq!(ada().adjust(0.05, logits([-1.0; 4])), 0.0);
+    }
+
+    #[test]
+    fn flip_mirrors_width() {
+        let images = Tensor::<TestBackend, 4>::from_floats([[[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]]]);
+        assert_eq!(flip(images).into_data().value, vec![3.0, 2.0, 1.0, 6.0, 5.0, 4.0]);
+    }
+
+    #[test]
+    fn zero_probability_leaves_images_unchanged() {
+        let images = Tensor::<TestBackend, 4>::from_floats([[[[1.0, 2.0], [3.0, 4.0]]]]);
+        let augmented = AugmentConfig::new().with_flip(true).augment(images.clone(), 0.0);
+        assert_eq!(augmented.into_data(), images.into_data());
+    }
+}
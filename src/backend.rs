@@ -0,0 +1,49 @@
+use clap::ValueEnum;
+
+/// Backends the binary can train on, selectable at runtime with `--backend`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// CPU only, works without any GPU or native library.
+    #[value(name = "ndarray")]
+    NdArray,
+    /// LibTorch on the CPU.
+    #[value(name = "tch-cpu")]
+    TchCpu,
+    /// Wgpu over OpenGl on the best available device.
+    #[value(name = "wgpu")]
+    Wgpu,
+    /// Wgpu with kernel fusion.
+    #[value(name = "wgpu-fusion")]
+    WgpuFusion,
+}
+
+/// Expands `$body` once per backend, with `$backend` bound to the autodiff backend type
+/// selected by `$kind` and `$device` to its device.
+macro_rules! with_backend {
+    ($kind:expr, |$backend:ident, $device:ident| $body:expr) => {
+        match $kind {
+            $crate::backend::BackendKind::NdArray => {
+                type $backend = burn::backend::Autodiff<burn::backend::NdArray<f32>>;
+                let $device = burn::backend::ndarray::NdArrayDevice::Cpu;
+                $body
+            }
+            $crate::backend::BackendKind::TchCpu => {
+                type $backend = burn::backend::Autodiff<burn::backend::LibTorch<f32>>;
+                let $device = burn::backend::libtorch::LibTorchDevice::Cpu;
+                $body
+            }
+            $crate::backend::BackendKind::Wgpu => {
+                type $backend = burn::backend::Autodiff<burn::backend::Wgpu<burn::backend::wgpu::OpenGl, f32, i32>>;
+                let $device = burn::backend::wgpu::WgpuDevice::BestAvailable;
+                $body
+            }
+            $crate::backend::BackendKind::WgpuFusion => {
+                type $backend = burn::backend::Autodiff<burn::backend::Fusion<burn::backend::Wgpu<burn::backend::wgpu::OpenGl, f32, i32>>>;
+                let $device = burn::backend::wgpu::WgpuDevice::BestAvailable;
+                $body
+            }
+        }
+    };
+}
+
+pub(crate) use with_backend;
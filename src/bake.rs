@@ -0,0 +1,570 @@
+use std::{collections::HashSet, ffi::OsStr, fs::{self, File, OpenOptions}, io::{BufReader, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, time::Instant};
+
+use burn::{config::Config, data::dataset::SqliteDatasetWriter, tensor::{Data, DataSerialize, Shape}};
+use chrono::Local;
+use glob::Pattern;
+use image::{error::{ParameterError, ParameterErrorKind}, imageops::{self, FilterType}, io::Reader as ImageReader, DynamicImage, GenericImageView, ImageError, RgbImage};
+use rayon::prelude::*;
+
+use crate::data_loader::{TRAIN_SPLIT, VALID_SPLIT};
+
+/// Number of images decoded in parallel before they are appended to the staging files.
+const CHUNK_SIZE: usize = 512;
+/// File extensions picked up by the baker, compared case-insensitively.
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "bmp"];
+
+/// How a source image is brought to a square of `image_size` pixels.
+#[derive(Config, Debug)]
+pub enum Preprocessing {
+    /// Cut the largest centered square out of the image and resize it.
+    CenterCrop,
+    /// Fit the whole image into the square and fill the borders with black.
+    PadToSquare,
+    /// Resize to the square, ignoring the aspect ratio.
+    Stretch,
+    /// Cut a fixed box out of every image and resize it, images smaller than the box are skipped.
+    CropBox { x: u32, y: u32, width: u32, height: u32 },
+}
+
+impl Preprocessing {
+    /// The 178x178 face crop commonly used with the aligned 178x218 CelebA images.
+    pub fn celeba() -> Self {
+        Preprocessing::CropBox { x: 0, y: 20, width: 178, height: 178 }
+    }
+
+    fn apply(&self, img: DynamicImage, size: u32) -> Result<RgbImage, ImageError> {
+        let (width, height) = img.dimensions();
+        let img = match *self {
+            Preprocessing::CenterCrop => {
+                let side = width.min(height);
+                img.crop_imm((width - side) / 2, (height - side) / 2, side, side).resize_exact(size, size, FilterType::Lanczos3)
+            }
+            Preprocessing::PadToSquare => {
+                let fitted = img.resize(size, size, FilterType::Lanczos3).to_rgb8();
+                let mut canvas = RgbImage::new(size, size);
+                let (x, y) = ((size - fitted.width()) / 2, (size - fitted.height()) / 2);
+                imageops::overlay(&mut canvas, &fitted, x as i64, y as i64);
+                return Ok(canvas);
+            }
+            Preprocessing::Stretch => img.resize_exact(size, size, FilterType::Lanczos3),
+            Preprocessing::CropBox { x, y, width: box_width, height: box_height } => {
+                if x + box_width > width || y + box_height > height {
+                    return Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)));
+                }
+                img.crop_imm(x, y, box_width, box_height).resize_exact(size, size, FilterType::Lanczos3)
+            }
+        };
+        Ok(img.to_rgb8())
+    }
+}
+
+#[derive(Config, Debug)]
+pub struct BakeConfig {
+    /// Directory containing the source images.
+    pub input_dir: String,
+    /// Sqlite database the images are baked into.
+    pub db_file: String,
+    #[config(default = 64)]
+    pub image_size: usize,
+    #[config(default = "Preprocessing::CenterCrop")]
+    pub preprocessing: Preprocessing,
+    /// Fraction of the images held out in the validation split.
+    #[config(default = 0.0)]
+    pub valid_fraction: f64,
+    /// Seed of the train/validation assignment, the same seed always holds out the same images.
+    #[config(default = 0)]
+    pub split_seed: u64,
+    /// Number of decoding threads, 0 uses one per core.
+    #[config(default = 0)]
+    pub num_threads: usize,
+    /// Also bake images in subdirectories of `input_dir`.
+    #[config(default = false)]
+    pub recursive: bool,
+    /// Glob patterns relative to `input_dir`, an image is only baked if it matches one of them. Empty matches everything.
+    #[config(default = "Vec::new()")]
+    pub include: Vec<String>,
+    /// Glob patterns relative to `input_dir` of images to leave out.
+    #[config(default = "Vec::new()")]
+    pub exclude: Vec<String>,
+}
+
+/// Path of the json file next to the database recording the `BakeConfig` it was baked with.
+pub fn metadata_path(db_file: &str) -> String {
+    format!("{db_file}.meta.json")
+}
+
+/// Decodes all images of `config.input_dir` into the sqlite database `config.db_file`.
+///
+/// Decoded images are first appended to a staging directory next to the database, which is only
+/// turned into the database once every image was processed. With `resume` an interrupted bake
+/// continues from its staging directory instead of decoding everything again.
+///
+/// The database itself can not be resumed into: `SqliteDatasetWriter` refuses or deletes an existing
+/// file and writes to a temporary one that is removed when the process is interrupted, so a database
+/// only ever exists complete. Its rows also hold nothing but the pixels, so the images already in it
+/// could not be matched against the source files.
+pub fn bake_image_dataset(config: &BakeConfig, resume: bool, overwrite: bool) -> Result<(), String> {
+    if !(0.0..1.0).contains(&config.valid_fraction) {
+        return Err(format!("valid_fraction is {}, expected a fraction in [0, 1)", config.valid_fraction));
+    }
+    if Path::new(&config.db_file).exists() && !overwrite {
+        if resume {
+            return Err(format!("database {} is already complete and can not be extended, pass --overwrite to bake it again", config.db_file));
+        }
+        return Err(format!("database {} already exists, pass --overwrite to replace it", config.db_file));
+    }
+
+    let sources = collect_sources(config);
+    let mut staging = BakeStaging::open(config, resume)?;
+    let pending: Vec<&PathBuf> = sources.iter().filter(|path| !staging.contains(path)).collect();
+    println!(
+        "[{}]: Baking {} images, {} already staged from a previous run.",
+        Local::now(),
+        sources.len(),
+        sources.len() - pending.len()
+    );
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.num_threads)
+        .build()
+        .expect("Decoding thread pool should be created successfully");
+
+    let start_time = Instant::now();
+    let mut skipped: Vec<(&PathBuf, ImageError)> = Vec::new();
+    let mut decoded = 0;
+    for chunk in pending.chunks(CHUNK_SIZE) {
+        let results: Vec<_> = pool.install(|| {
+            chunk
+                .par_iter()
+                .map(|path| (*path, load_image(&Path::new(&config.input_dir).join(path), &config.preprocessing, config.image_size)))
+                .collect()
+        });
+        for (path, result) in results {
+            match result {
+                Ok(pixels) => {
+                    staging.append(path, &pixels);
+                    decoded += 1;
+                }
+                Err(err) => skipped.push((path, err)),
+            }
+        }
+        staging.flush();
+
+        let elapsed = start_time.elapsed().as_secs_f32();
+        println!(
+            "[{}]: Baked {}/{} images, {} skipped - {:.1} images/s",
+            Local::now(),
+            staging.len(),
+            sources.len(),
+            skipped.len(),
+            decoded as f32 / elapsed.max(f32::EPSILON),
+        );
+    }
+
+    let baked = staging.len();
+    let held_out = staging.write_database(config, overwrite);
+
+    println!("[{}]: Bake summary for {}:", Local::now(), config.db_file);
+    println!("  {baked} images baked, {decoded} decoded in this run, {} skipped.", skipped.len());
+    println!("  {} images in split \"{TRAIN_SPLIT}\", {held_out} in split \"{VALID_SPLIT}\".", baked - held_out);
+    for (path, err) in &skipped {
+        println!("  Skipped {path:?}: {err}");
+    }
+    Ok(())
+}
+
+/// Lists the images to bake relative to `input_dir`, sorted so the rows of the database do not depend on the file system.
+fn collect_sources(config: &BakeConfig) -> Vec<PathBuf> {
+    let compile = |patterns: &[String]| -> Vec<Pattern> {
+        patterns
+            .iter()
+            .map(|pattern| Pattern::new(pattern).unwrap_or_else(|err| panic!("Invalid glob pattern {pattern}: {err}")))
+            .collect()
+    };
+    let include = compile(&config.include);
+    let exclude = compile(&config.exclude);
+
+    let input_dir = Path::new(&config.input_dir);
+    if let Err(err) = fs::read_dir(input_dir) {
+        panic!("Input directory {} should be readable: {err}", config.input_dir);
+    }
+
+    let mut sources = Vec::new();
+    let mut directories = vec![input_dir.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(err) => {
+                println!("Skipping unreadable directory {directory:?}: {err}");
+                continue;
+            }
+        };
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if path.is_dir() {
+                if config.recursive {
+                    directories.push(path);
+                }
+                continue;
+            }
+            if !has_image_extension(&path) {
+                continue;
+            }
+            let relative = path.strip_prefix(input_dir).expect("Listed images should be inside the input directory");
+            let included = include.is_empty() || include.iter().any(|pattern| pattern.matches_path(relative));
+            let excluded = exclude.iter().any(|pattern| pattern.matches_path(relative));
+            if included && !excluded {
+                sources.push(relative.to_path_buf());
+            }
+        }
+    }
+    sources.sort();
+    sources
+}
+
+fn has_image_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|extension| IMAGE_EXTENSIONS.iter().any(|known| extension.eq_ignore_ascii_case(known)))
+}
+
+/// Split an image is baked into, decided by a hash of its path relative to `input_dir` and the split seed.
+///
+/// The assignment only depends on the image itself, so adding or removing images never moves the others.
+fn split_of(relative: &Path, config: &BakeConfig) -> &'static str {
+    // FNV-1a, unlike the std hasher it is stable across Rust versions.
+    let hash = relative
+        .to_string_lossy()
+        .bytes()
+        .chain(config.split_seed.to_le_bytes())
+        .fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    if (hash as f64 / u64::MAX as f64) < config.valid_fraction {
+        VALID_SPLIT
+    } else {
+        TRAIN_SPLIT
+    }
+}
+
+/// Decodes and preprocesses one image into `[image_size, image_size, 3]` pixels.
+fn load_image(path: &Path, preprocessing: &Preprocessing, image_size: usize) -> Result<Vec<u8>, ImageError> {
+    // The content decides the format, so mislabeled files still decode.
+    let img = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    Ok(preprocessing.apply(img, image_size as u32)?.into_raw())
+}
+
+/// Append-only store of decoded images for an unfinished bake.
+///
+/// `pixels.bin` holds the raw images back to back and `sources.txt` the matching source path relative
+/// to `input_dir` per line. Pixels are written before their source, so after a crash both are cut back
+/// to the shorter one. `bake.json` records the config of the bake, which a resumed bake has to select
+/// the same images with.
+struct BakeStaging {
+    dir: PathBuf,
+    item_size: usize,
+    pixels: File,
+    sources: File,
+    staged: HashSet<PathBuf>,
+    /// Staged sources in the order of their pixels.
+    order: Vec<PathBuf>,
+}
+
+impl BakeStaging {
+    fn open(config: &BakeConfig, resume: bool) -> Result<Self, String> {
+        let dir = PathBuf::from(format!("{}.partial", config.db_file));
+        // Differently spelled paths to the same directory select the same images.
+        let input_dir = fs::canonicalize(&config.input_dir).unwrap_or_else(|_| PathBuf::from(&config.input_dir));
+        if dir.exists() {
+            if !resume {
+                return Err(format!("an unfinished bake exists at {dir:?}, pass --resume to continue it or delete the directory"));
+            }
+            let previous = BakeConfig::load(dir.join("bake.json")).map_err(|err| format!("staged bake config should be readable: {err}"))?;
+            let mismatches = [
+                ("input directory", previous.input_dir != input_dir.to_string_lossy()),
+                ("recursive", previous.recursive != config.recursive),
+                ("include patterns", previous.include != config.include),
+                ("exclude patterns", previous.exclude != config.exclude),
+                ("image size", previous.image_size != config.image_size),
+                ("preprocessing", previous.preprocessing.to_string() != config.preprocessing.to_string()),
+            ];
+            if let Some((field, _)) = mismatches.iter().find(|(_, mismatch)| *mismatch) {
+                return Err(format!("the unfinished bake at {dir:?} used a different {field}, bake with the same sources or delete the directory"));
+            }
+        } else {
+            fs::create_dir_all(&dir).expect("Staging directory should be created successfully");
+            let staged_config = BakeConfig { input_dir: input_dir.to_string_lossy().into_owned(), ..config.clone() };
+            staged_config.save(dir.join("bake.json")).expect("Bake config should be saved successfully");
+        }
+
+        let item_size = config.image_size * config.image_size * 3;
+        let mut sources: Vec<PathBuf> = fs::read_to_string(dir.join("sources.txt"))
+            .unwrap_or_default()
+            .lines()
+            .map(PathBuf::from)
+            .collect();
+        let pixels = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(dir.join("pixels.bin"))
+            .expect("Staged pixels should be readable");
+        let pixel_items = pixels.metadata().expect("Staged pixels should be readable").len() as usize / item_size;
+        let complete = pixel_items.min(sources.len());
+        sources.truncate(complete);
+        pixels.set_len((complete * item_size) as u64).expect("Staged pixels should be writable");
+
+        let mut sources_file = File::create(dir.join("sources.txt")).expect("Staged sources should be writable");
+        for source in &sources {
+            writeln!(sources_file, "{}", source.display()).expect("Staged sources should be writable");
+        }
+
+        let mut staging = Self {
+            dir,
+            item_size,
+            pixels,
+            sources: sources_file,
+            staged: sources.iter().cloned().collect(),
+            order: sources,
+        };
+        staging.pixels.seek(SeekFrom::End(0)).expect("Staged pixels should be writable");
+        Ok(staging)
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.staged.contains(path)
+    }
+
+    fn len(&self) -> usize {
+        self.staged.len()
+    }
+
+    fn append(&mut self, path: &Path, pixels: &[u8]) {
+        self.pixels.write_all(pixels).expect("Staged pixels should be writable");
+        writeln!(self.sources, "{}", path.display()).expect("Staged sources should be writable");
+        self.staged.insert(path.to_path_buf());
+        self.order.push(path.to_path_buf());
+    }
+
+    fn flush(&mut self) {
+        self.pixels.sync_data().expect("Staged pixels should be writable");
+        self.sources.sync_data().expect("Staged sources should be writable");
+    }
+
+    /// Writes every staged image into the database and removes the staging directory.
+    ///
+    /// Returns the number of images held out in the validation split.
+    fn write_database(mut self, config: &BakeConfig, overwrite: bool) -> usize {
+        let mut writer: SqliteDatasetWriter<DataSerialize<u8>> = SqliteDatasetWriter::new(&config.db_file, overwrite).unwrap();
+
+        self.pixels.seek(SeekFrom::Start(0)).expect("Staged pixels should be readable");
+        let mut reader = BufReader::new(&self.pixels);
+        let mut img_buf = vec![0; self.item_size];
+        let mut held_out = 0;
+        for source in &self.order {
+            reader.read_exact(&mut img_buf).expect("Staged pixels should be readable");
+            let data: Data<u8, 3> = Data::new(img_buf.clone(), Shape::new([config.image_size, config.image_size, 3]));
+
+            // Insert into sqlite
+            let split = split_of(source, config);
+            if split == VALID_SPLIT {
+                held_out += 1;
+            }
+            writer.write(split, &data.serialize()).unwrap();
+        }
+        writer.set_completed().unwrap();
+        config.save(metadata_path(&config.db_file)).expect("Dataset metadata should be saved successfully");
+
+        fs::remove_dir_all(&self.dir).expect("Staging directory should be removed successfully");
+        held_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::data::dataset::Dataset;
+
+    use super::*;
+    use crate::data_loader::make_image_dataset;
+
+    fn config(valid_fraction: f64, split_seed: u64) -> BakeConfig {
+        BakeConfig::new("images".to_string(), "images.sqlite".to_string())
+            .with_valid_fraction(valid_fraction)
+            .with_split_seed(split_seed)
+    }
+
+    fn paths() -> Vec<PathBuf> {
+        (0..1000).map(|index| PathBuf::from(format!("{index}.png"))).collect()
+    }
+
+    fn valid_count(config: &BakeConfig) -> usize {
+        paths().iter().filter(|path| split_of(path, config) == VALID_SPLIT).count()
+    }
+
+    /// Directory with `count` small png images and the config baking them into a database next to it.
+    fn image_dir(name: &str, count: usize) -> (PathBuf, BakeConfig) {
+        let dir = std::env::temp_dir().join(format!("gamma-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("images")).unwrap();
+        for index in 0..count {
+            RgbImage::from_pixel(4, 4, image::Rgb([index as u8; 3])).save(dir.join(format!("images/{index}.png"))).unwrap();
+        }
+        let config = BakeConfig::new(dir.join("images").to_str().unwrap().to_string(), dir.join("images.sqlite").to_str().unwrap().to_string()).with_image_size(2);
+        (dir, config)
+    }
+
+    #[test]
+    fn staging_resumes_from_the_complete_images_after_a_crash() {
+        let (dir, config) = image_dir("staging", 0);
+        let item_size = 2 * 2 * 3;
+        let mut staging = BakeStaging::open(&config, false).unwrap();
+        staging.append(Path::new("a.png"), &[1; 12]);
+        staging.append(Path::new("b.png"), &[2; 12]);
+        // Crash after the pixels of the third image but before its source.
+        staging.pixels.write_all(&[3; 12]).unwrap();
+        staging.flush();
+        drop(staging);
+
+        let fresh = BakeStaging::open(&config, false).map(|_| ());
+        let staging = BakeStaging::open(&config, true).unwrap();
+        let pixels_len = staging.pixels.metadata().unwrap().len();
+        let len = staging.len();
+        let contains_a = staging.contains(Path::new("a.png"));
+        drop(staging);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(fresh.is_err(), "an unfinished bake should only be continued with resume");
+        assert_eq!(len, 2);
+        assert!(contains_a);
+        assert_eq!(pixels_len, 2 * item_size as u64);
+    }
+
+    #[test]
+    fn resumed_bake_matches_staged_images_under_a_differently_spelled_input_dir() {
+        let (dir, config) = image_dir("resume-spelling", 3);
+        let mut staging = BakeStaging::open(&config, false).unwrap();
+        let pixels = load_image(&dir.join("images/0.png"), &config.preprocessing, config.image_size).unwrap();
+        staging.append(Path::new("0.png"), &pixels);
+        staging.flush();
+        drop(staging);
+
+        let respelled = BakeConfig { input_dir: dir.join("images/../images").to_str().unwrap().to_string(), ..config.clone() };
+        let result = bake_image_dataset(&respelled, true, false);
+        let rows = make_image_dataset(&config.db_file, TRAIN_SPLIT).map(|dataset| dataset.len());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result, Ok(()));
+        assert_eq!(rows, Some(3));
+    }
+
+    #[test]
+    fn resumed_bake_rejects_different_sources() {
+        let (dir, config) = image_dir("resume-sources", 1);
+        drop(BakeStaging::open(&config, false).unwrap());
+
+        let changed = [
+            BakeConfig { input_dir: dir.to_str().unwrap().to_string(), ..config.clone() },
+            config.clone().with_recursive(true),
+            config.clone().with_include(vec!["*.png".to_string()]),
+            config.clone().with_exclude(vec!["0.png".to_string()]),
+            config.clone().with_image_size(4),
+            config.clone().with_preprocessing(Preprocessing::Stretch),
+        ];
+        let results: Vec<_> = changed.iter().map(|changed| BakeStaging::open(changed, true).map(|_| ())).collect();
+        let same = BakeStaging::open(&config, true).map(|_| ());
+        fs::remove_dir_all(&dir).unwrap();
+        for (changed, result) in changed.iter().zip(results) {
+            assert!(result.is_err(), "{changed} should not resume the bake");
+        }
+        assert_eq!(same, Ok(()));
+    }
+
+    /// Image of `width` x `height` with the rows `rows` white and the rest black.
+    fn image_with_white_rows(width: u32, height: u32, rows: std::ops::Range<u32>) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |_, y| image::Rgb([if rows.contains(&y) { 255 } else { 0 }; 3])))
+    }
+
+    fn white_image(width: u32, height: u32) -> DynamicImage {
+        image_with_white_rows(width, height, 0..height)
+    }
+
+    /// Rows of `img` whose pixels are all of the given brightness.
+    fn rows_of(img: &RgbImage, value: u8) -> Vec<u32> {
+        (0..img.height()).filter(|&y| (0..img.width()).all(|x| img.get_pixel(x, y).0 == [value; 3])).collect()
+    }
+
+    #[test]
+    fn center_crop_fills_the_square() {
+        // Only the centered square is white, the crop should contain nothing else.
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(30, 10, |x, _| image::Rgb([if (10..20).contains(&x) { 255 } else { 0 }; 3])));
+        let cropped = Preprocessing::CenterCrop.apply(img, 8).unwrap();
+        assert_eq!(cropped.dimensions(), (8, 8));
+        assert_eq!(rows_of(&cropped, 255), (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn pad_to_square_keeps_black_borders() {
+        let padded = Preprocessing::PadToSquare.apply(white_image(20, 10), 8).unwrap();
+        assert_eq!(padded.dimensions(), (8, 8));
+        assert_eq!(rows_of(&padded, 0), vec![0, 1, 6, 7]);
+        assert_eq!(rows_of(&padded, 255), vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn stretch_resizes_to_the_exact_size() {
+        let stretched = Preprocessing::Stretch.apply(white_image(30, 10), 8).unwrap();
+        assert_eq!(stretched.dimensions(), (8, 8));
+        assert_eq!(rows_of(&stretched, 255), (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn crop_box_rejects_images_smaller_than_the_box() {
+        let crop = Preprocessing::CropBox { x: 10, y: 0, width: 20, height: 20 };
+        assert!(crop.apply(white_image(29, 20), 8).is_err());
+        assert!(crop.apply(white_image(30, 19), 8).is_err());
+        assert!(crop.apply(white_image(30, 20), 8).is_ok());
+    }
+
+    #[test]
+    fn celeba_crops_the_face_rows() {
+        let cropped = Preprocessing::celeba().apply(image_with_white_rows(178, 218, 20..198), 16).unwrap();
+        assert_eq!(rows_of(&cropped, 255), (0..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cropping_modes_leave_no_black_rows() {
+        // An aspect-preserving resize of an almost square image rounds one side down and leaves a black row.
+        for (width, height) in [(101, 100), (100, 101), (179, 178), (178, 217)] {
+            for preprocessing in [Preprocessing::CenterCrop, Preprocessing::Stretch, Preprocessing::CropBox { x: 1, y: 0, width: 99, height: 100 }] {
+                let img = preprocessing.apply(white_image(width, height), 64).unwrap();
+                assert_eq!(img.dimensions(), (64, 64));
+                assert_eq!(rows_of(&img, 255).len(), 64, "{preprocessing} of a {width}x{height} image has non-white rows");
+            }
+        }
+    }
+
+    #[test]
+    fn has_image_extension_ignores_case_and_other_files() {
+        for name in ["a.jpg", "a.JPEG", "a.png", "a.WebP", "a.bmp", "dir/a.b.png"] {
+            assert!(has_image_extension(Path::new(name)), "{name} should be baked");
+        }
+        for name in ["a.gif", "a.txt", "png", "a.png.bak", ".png"] {
+            assert!(!has_image_extension(Path::new(name)), "{name} should be skipped");
+        }
+    }
+
+    #[test]
+    fn split_of_holds_out_roughly_the_valid_fraction() {
+        assert_eq!(valid_count(&config(0.0, 0)), 0);
+        assert_eq!(valid_count(&config(1.0, 0)), 1000);
+        let held_out = valid_count(&config(0.2, 0));
+        assert!((150..250).contains(&held_out), "{held_out} of 1000 images held out");
+    }
+
+    #[test]
+    fn split_of_depends_on_relative_path_and_seed() {
+        let config = config(0.5, 7);
+        let splits: Vec<_> = paths().iter().map(|path| split_of(path, &config)).collect();
+        let elsewhere = BakeConfig { input_dir: "/data/images".to_string(), ..config.clone() };
+        let moved: Vec<_> = paths().iter().map(|path| split_of(path, &elsewhere)).collect();
+        assert_eq!(splits, moved);
+        let reseeded = BakeConfig { split_seed: 8, ..config };
+        assert!(paths().iter().zip(&splits).any(|(path, split)| split_of(path, &reseeded) != *split));
+    }
+}
@@ -0,0 +1,90 @@
+use burn::config::Config;
+
+/// Adapts the number of discriminator updates per step to how far ahead the discriminator is.
+///
+/// The lead is the difference of the running means of D(x) and D(G(z)), both as probabilities.
+/// A discriminator that separates real and generated images too well skips its update, one that
+/// can barely tell them apart gets additional updates on the same batch.
+#[derive(Config, Debug)]
+pub struct BalanceConfig {
+    /// Decay of the running means per step.
+    #[config(default = 0.9)]
+    pub momentum: f64,
+    /// Skip discriminator updates while the lead is above this.
+    #[config(default = 0.6)]
+    pub skip_above: f64,
+    /// Repeat discriminator updates while the lead is below this.
+    #[config(default = 0.1)]
+    pub repeat_below: f64,
+    /// Additional discriminator updates in a step that repeats.
+    #[config(default = 1)]
+    pub repeats: usize,
+}
+
+impl BalanceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..1.0).contains(&self.momentum) {
+            return Err(format!("balance momentum is {}, expected a value in [0, 1)", self.momentum));
+        }
+        if self.repeat_below >= self.skip_above {
+            return Err(format!("balance repeat_below ({}) has to be below skip_above ({})", self.repeat_below, self.skip_above));
+        }
+        Ok(())
+    }
+
+    /// Discriminator updates for a step, from the running means before the step.
+    pub fn discriminator_updates(&self, running: &RunningDiscriminator) -> usize {
+        let lead = running.real - running.fake;
+        if lead > self.skip_above {
+            0
+        } else if lead < self.repeat_below {
+            1 + self.repeats
+        } else {
+            1
+        }
+    }
+}
+
+/// Running means of D(x) and D(G(z)) as probabilities, kept in the checkpoints.
+#[derive(Config, Debug)]
+pub struct RunningDiscriminator {
+    #[config(default = 0.5)]
+    pub real: f64,
+    #[config(default = 0.5)]
+    pub fake: f64,
+}
+
+impl RunningDiscriminator {
+    pub fn update(&mut self, real: f64, fake: f64, momentum: f64) {
+        self.real = momentum * self.real + (1.0 - momentum) * real;
+        self.fake = momentum * self.fake + (1.0 - momentum) * fake;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running(real: f64, fake: f64) -> RunningDiscriminator {
+        RunningDiscriminator { real, fake }
+    }
+
+    #[test]
+    fn update_moves_running_means_by_momentum() {
+        let mut means = RunningDiscriminator::new();
+        means.update(1.0, 0.0, 0.9);
+        assert!((means.real - 0.55).abs() < 1e-12);
+        assert!((means.fake - 0.45).abs() < 1e-12);
+        means.update(1.0, 0.0, 0.0);
+        assert_eq!((means.real, means.fake), (1.0, 0.0));
+    }
+
+    #[test]
+    fn discriminator_updates_follow_the_lead() {
+        let config = BalanceConfig::new().with_repeats(2);
+        assert_eq!(config.discriminator_updates(&running(0.9, 0.1)), 0);
+        assert_eq!(config.discriminator_updates(&running(0.7, 0.4)), 1);
+        assert_eq!(config.discriminator_updates(&running(0.5, 0.5)), 3);
+        assert_eq!(config.discriminator_updates(&running(0.3, 0.6)), 3);
+    }
+}
@@ -0,0 +1,427 @@
+use std::{collections::HashSet, fs, path::PathBuf, time::Duration};
+
+use burn::{config::Config, module::Module, record::{CompactRecorder, Recorder}, tensor::backend::{AutodiffBackend, Backend}};
+
+use crate::{balance::RunningDiscriminator, models::{Discriminator, Generator}, optimizer::ModuleOptimizer, training::TrainingConfig};
+
+/// When checkpoints are written during training.
+#[derive(Config, Debug)]
+pub enum CheckpointInterval {
+    /// After every `steps` steps.
+    Steps { steps: usize },
+    /// After the first step that ends at least `minutes` after the previous checkpoint, or after the start of the run.
+    Minutes { minutes: f64 },
+}
+
+/// Value a checkpoint is ranked by for `keep_best`, lower is better.
+///
+/// Averaged over the steps since the previous checkpoint, as single batches are too noisy to compare.
+#[derive(Config, Debug, Copy, PartialEq)]
+pub enum CheckpointMetric {
+    GeneratorLoss,
+    DiscriminatorLoss,
+    /// |D(x) - D(G(z))| as probabilities, small when the discriminator rates generated and real images alike,
+    /// whichever of them it rates higher.
+    DiscriminatorGap,
+}
+
+/// When checkpoints are written and which of them are kept on disk.
+///
+/// A checkpoint is kept if any rule keeps it, the most recent one is always kept. With every rule
+/// turned off no checkpoint is deleted.
+#[derive(Config, Debug)]
+pub struct CheckpointConfig {
+    #[config(default = "CheckpointInterval::Steps { steps: 100 }")]
+    pub interval: CheckpointInterval,
+    /// Keep the `keep_last` most recently written checkpoints, 0 turns this off.
+    #[config(default = 5)]
+    pub keep_last: usize,
+    /// Also keep every `keep_every`-th written checkpoint, 0 turns this off.
+    #[config(default = 0)]
+    pub keep_every: usize,
+    /// Also keep the `keep_best` checkpoints with the lowest `metric`, 0 turns this off.
+    #[config(default = 0)]
+    pub keep_best: usize,
+    #[config(default = "CheckpointMetric::GeneratorLoss")]
+    pub metric: CheckpointMetric,
+}
+
+/// A checkpoint listed in the manifest.
+#[derive(Config, Debug)]
+pub struct CheckpointEntry {
+    pub tag: String,
+    pub global_step: usize,
+    /// Position in the order the checkpoints were written in.
+    pub index: usize,
+    /// The `CheckpointMetric` of the run, `None` if no step since the previous checkpoint reported it.
+    pub metric: Option<f64>,
+}
+
+/// The checkpoints of a run that are still on disk, saved as `latest.json` in the artifact directory.
+#[derive(Config, Debug)]
+pub struct CheckpointManifest {
+    /// Tag of the most recently written checkpoint.
+    pub latest: String,
+    /// Number of checkpoints written over the run, deleted ones included.
+    pub written: usize,
+    pub checkpoints: Vec<CheckpointEntry>,
+}
+
+fn manifest_path(artifact_dir: &str) -> String {
+    format!("{artifact_dir}/latest.json")
+}
+
+impl CheckpointConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.interval {
+            CheckpointInterval::Steps { steps: 0 } => Err("checkpoint interval needs at least 1 step".to_string()),
+            CheckpointInterval::Minutes { minutes } if !(minutes.is_finite() && minutes > 0.0) => Err(format!("checkpoint interval is {minutes} minutes, expected a positive value")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether to write a checkpoint after `global_step` steps, `elapsed` after the previous checkpoint.
+    pub fn is_due(&self, global_step: usize, elapsed: Duration) -> bool {
+        match self.interval {
+            CheckpointInterval::Steps { steps } => global_step.is_multiple_of(steps),
+            CheckpointInterval::Minutes { minutes } => elapsed.as_secs_f64() >= minutes * 60.0,
+        }
+    }
+
+    /// Records the just written checkpoint `tag` in the manifest of `artifact_dir` and deletes the checkpoints no rule keeps anymore.
+    pub fn retain(&self, artifact_dir: &str, tag: &str, global_step: usize, metric: Option<f64>) {
+        let path = manifest_path(artifact_dir);
+        let mut manifest = CheckpointManifest::load(&path).unwrap_or_else(|_| CheckpointManifest::new(tag.to_string(), 0, Vec::new()));
+        // A run resumed from an older checkpoint writes the same tags again, the records were just overwritten.
+        manifest.checkpoints.retain(|entry| entry.tag != tag);
+        manifest.checkpoints.push(CheckpointEntry { tag: tag.to_string(), global_step, index: manifest.written, metric });
+        manifest.written += 1;
+        manifest.latest = tag.to_string();
+
+        let kept = self.kept(&manifest.checkpoints);
+        let (kept, deleted): (Vec<_>, Vec<_>) = manifest.checkpoints.into_iter().partition(|entry| kept.contains(&entry.index));
+        manifest.checkpoints = kept;
+        // Replacing the manifest in one rename and deleting afterwards, it never lists a checkpoint that is gone.
+        let temporary_path = format!("{path}.tmp");
+        manifest.save(&temporary_path).expect("Checkpoint manifest should be saved successfully");
+        fs::rename(&temporary_path, &path).expect("Checkpoint manifest should be saved successfully");
+        for entry in deleted {
+            delete_checkpoint(artifact_dir, &entry.tag);
+        }
+    }
+
+    /// Indices of the checkpoints the rules keep.
+    fn kept(&self, checkpoints: &[CheckpointEntry]) -> HashSet<usize> {
+        if self.keep_last == 0 && self.keep_every == 0 && self.keep_best == 0 {
+            return checkpoints.iter().map(|entry| entry.index).collect();
+        }
+        let mut by_index: Vec<_> = checkpoints.iter().collect();
+        by_index.sort_by_key(|entry| std::cmp::Reverse(entry.index));
+        let mut kept: HashSet<usize> = by_index.iter().take(self.keep_last.max(1)).map(|entry| entry.index).collect();
+        if self.keep_every > 0 {
+            kept.extend(checkpoints.iter().filter(|entry| (entry.index + 1) % self.keep_every == 0).map(|entry| entry.index));
+        }
+        let mut by_metric: Vec<_> = checkpoints.iter().filter_map(|entry| Some((entry.metric?, entry.index))).collect();
+        by_metric.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        kept.extend(by_metric.into_iter().take(self.keep_best).map(|(_, index)| index));
+        kept
+    }
+}
+
+impl CheckpointMetric {
+    /// Value of the metric for one step, `None` if the step has none (no generator update).
+    pub fn of_step(&self, generator_loss: Option<f32>, discriminator_loss: f32, real_probability: f64, fake_probability: f64) -> Option<f64> {
+        match self {
+            CheckpointMetric::GeneratorLoss => generator_loss.map(|loss| loss as f64),
+            CheckpointMetric::DiscriminatorLoss => Some(discriminator_loss as f64),
+            CheckpointMetric::DiscriminatorGap => Some((real_probability - fake_probability).abs()),
+        }
+    }
+}
+
+/// Everything besides the model and optimizer records that is needed to continue a run.
+///
+/// The backend RNG is reseeded from `config.seed + global_step` before every step,
+/// so `global_step` doubles as the position of the random number generator.
+#[derive(Config)]
+pub struct TrainingState {
+    pub config: TrainingConfig,
+    /// Epoch the checkpoint was taken in, starting at 1.
+    pub epoch: usize,
+    /// Last finished iteration inside `epoch`.
+    pub iteration: usize,
+    /// Number of finished steps over the whole run.
+    pub global_step: usize,
+    /// Scheduled learning rates of the last finished step, for reference; resumed runs recompute them from `global_step`.
+    pub generator_learning_rate: f64,
+    pub discriminator_learning_rate: f64,
+    /// Statistics the adaptive balancing decides on.
+    #[config(default = "RunningDiscriminator::new()")]
+    pub running_discriminator: RunningDiscriminator,
+    /// Probability of each augmentation, tuned during training by ADA.
+    #[config(default = 0.0)]
+    pub augment_probability: f64,
+    /// Number of discriminator updates over the whole run, the lazy penalties are due on every `regularization_interval`th.
+    #[config(default = 0)]
+    pub discriminator_steps: usize,
+}
+
+/// Checkpoints are named by the number of finished steps, which unlike the iteration does not restart every epoch.
+pub fn checkpoint_tag(global_step: usize) -> String {
+    global_step.to_string()
+}
+
+fn state_path(artifact_dir: &str, tag: &str) -> String {
+    format!("{artifact_dir}/state-{tag}.json")
+}
+
+/// Record of the optimizer of network `name` ("gen" or "dis") in checkpoint `tag`.
+pub fn optimizer_path(artifact_dir: &str, name: &str, tag: &str) -> PathBuf {
+    format!("{artifact_dir}/optimizer_{name}-{tag}").into()
+}
+
+pub fn save_checkpoint<B: AutodiffBackend>(
+    artifact_dir: &str,
+    state: &TrainingState,
+    generator: &Generator<B>,
+    generator_ema: &Generator<B::InnerBackend>,
+    discriminator: &Discriminator<B>,
+    optimizer_gen: &dyn ModuleOptimizer<Generator<B>, B>,
+    optimizer_dis: &dyn ModuleOptimizer<Discriminator<B>, B>,
+) {
+    let tag = checkpoint_tag(state.global_step);
+    let recorder = CompactRecorder::new();
+    generator
+        .clone()
+        .save_file(format!("{artifact_dir}/generator-{tag}"), &recorder)
+        .expect("Generator model should be saved successfully");
+    generator_ema
+        .clone()
+        .save_file(format!("{artifact_dir}/generator_ema-{tag}"), &recorder)
+        .expect("Generator average should be saved successfully");
+    discriminator
+        .clone()
+        .save_file(format!("{artifact_dir}/discriminator-{tag}"), &recorder)
+        .expect("Discriminator model should be saved successfully");
+    optimizer_gen.save(optimizer_path(artifact_dir, "gen", &tag));
+    optimizer_dis.save(optimizer_path(artifact_dir, "dis", &tag));
+    // The state is written last, so a checkpoint only shows up once all of its records exist.
+    state
+        .save(state_path(artifact_dir, &tag))
+        .expect("Training state should be saved successfully");
+}
+
+pub fn load_state(artifact_dir: &str, tag: &str) -> TrainingState {
+    TrainingState::load(state_path(artifact_dir, tag))
+        .unwrap_or_else(|err| panic!("Training state of checkpoint {tag} should be readable: {err}"))
+}
+
+/// Loads the records of checkpoint `tag` into freshly initialized models.
+///
+/// The optimizers load their records when they are built, see `optimizer_path`.
+pub fn load_checkpoint<B: AutodiffBackend>(artifact_dir: &str, tag: &str, generator: Generator<B>, discriminator: Discriminator<B>) -> (Generator<B>, Discriminator<B>) {
+    let generator = load_generator(artifact_dir, tag, generator);
+    let discriminator = discriminator.load_record(
+        CompactRecorder::new()
+            .load(format!("{artifact_dir}/discriminator-{tag}").into())
+            .expect("Discriminator record should be loaded successfully"),
+    );
+    (generator, discriminator)
+}
+
+/// Loads only the generator of checkpoint `tag`, e.g. for sampling on a backend without autodiff.
+pub fn load_generator<B: Backend>(artifact_dir: &str, tag: &str, generator: Generator<B>) -> Generator<B> {
+    generator.load_record(
+        CompactRecorder::new()
+            .load(format!("{artifact_dir}/generator-{tag}").into())
+            .expect("Generator record should be loaded successfully"),
+    )
+}
+
+/// Loads the moving average of the generator of checkpoint `tag`, `None` if the checkpoint has none.
+pub fn load_generator_ema<B: Backend>(artifact_dir: &str, tag: &str, generator: Generator<B>) -> Option<Generator<B>> {
+    let record = CompactRecorder::new()
+        .load(format!("{artifact_dir}/generator_ema-{tag}").into())
+        .ok()?;
+    Some(generator.load_record(record))
+}
+
+/// Deletes every record of checkpoint `tag`, the state first so a partly deleted checkpoint is not picked up.
+fn delete_checkpoint(artifact_dir: &str, tag: &str) {
+    fs::remove_file(state_path(artifact_dir, tag)).ok();
+    let prefixes = ["generator", "generator_ema", "discriminator", "optimizer_gen", "optimizer_dis"].map(|name| format!("{name}-{tag}."));
+    let Ok(entries) = fs::read_dir(artifact_dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name();
+        if name.to_str().is_some_and(|name| prefixes.iter().any(|prefix| name.starts_with(prefix.as_str()))) {
+            fs::remove_file(entry.path()).unwrap_or_else(|err| panic!("Record {} should be deleted successfully: {err}", entry.path().display()));
+        }
+    }
+}
+
+/// Finds the most recent checkpoint in `artifact_dir`: the one `latest.json` points at, or the one with the highest global step.
+pub fn latest_checkpoint(artifact_dir: &str) -> Option<String> {
+    if let Ok(manifest) = CheckpointManifest::load(manifest_path(artifact_dir)) {
+        if fs::metadata(state_path(artifact_dir, &manifest.latest)).is_ok() {
+            return Some(manifest.latest);
+        }
+    }
+    fs::read_dir(artifact_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_prefix("state-")?.strip_suffix(".json")?.parse::<usize>().ok()
+        })
+        .max()
+        .map(checkpoint_tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{DiscriminatorConfig, GeneratorConfig};
+
+    use super::*;
+
+    /// Checkpoints with index 0 to `metrics.len() - 1`, every 10 steps.
+    fn checkpoints(metrics: &[Option<f64>]) -> Vec<CheckpointEntry> {
+        metrics
+            .iter()
+            .enumerate()
+            .map(|(index, metric)| CheckpointEntry { tag: checkpoint_tag(10 * (index + 1)), global_step: 10 * (index + 1), index, metric: *metric })
+            .collect()
+    }
+
+    fn kept(config: CheckpointConfig, checkpoints: &[CheckpointEntry]) -> Vec<usize> {
+        let mut kept: Vec<_> = config.kept(checkpoints).into_iter().collect();
+        kept.sort();
+        kept
+    }
+
+    fn policy(keep_last: usize, keep_every: usize, keep_best: usize) -> CheckpointConfig {
+        CheckpointConfig::new().with_keep_last(keep_last).with_keep_every(keep_every).with_keep_best(keep_best)
+    }
+
+    /// Writes empty records and a placeholder state for the checkpoint of `global_step`.
+    fn write_checkpoint(artifact_dir: &str, global_step: usize) {
+        let tag = checkpoint_tag(global_step);
+        for name in ["generator", "generator_ema", "discriminator", "optimizer_gen", "optimizer_dis"] {
+            fs::write(format!("{artifact_dir}/{name}-{tag}.mpk.gz"), []).unwrap();
+        }
+        fs::write(state_path(artifact_dir, &tag), "{}").unwrap();
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gamma-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn keep_last_keeps_most_recently_written() {
+        let checkpoints = checkpoints(&[None; 6]);
+        assert_eq!(kept(policy(2, 0, 0), &checkpoints), [4, 5]);
+        assert_eq!(kept(policy(10, 0, 0), &checkpoints), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn keep_every_keeps_every_nth_written() {
+        let checkpoints = checkpoints(&[None; 7]);
+        assert_eq!(kept(policy(1, 3, 0), &checkpoints), [2, 5, 6]);
+    }
+
+    #[test]
+    fn keep_best_keeps_lowest_metrics_and_the_latest() {
+        let checkpoints = checkpoints(&[Some(0.5), Some(0.1), None, Some(0.9), Some(0.2), Some(0.7)]);
+        assert_eq!(kept(policy(0, 0, 2), &checkpoints), [1, 4, 5]);
+        assert_eq!(kept(policy(1, 0, 1), &checkpoints), [1, 5]);
+    }
+
+    #[test]
+    fn discriminator_gap_ranks_by_distance_from_even() {
+        let gap = |real, fake| CheckpointMetric::DiscriminatorGap.of_step(None, 0.0, real, fake).unwrap();
+        // The discriminator rating generated images higher than real ones is as far off as the other way around.
+        assert!((gap(0.3, 0.7) - 0.4).abs() < 1e-12);
+        assert!((gap(0.7, 0.3) - 0.4).abs() < 1e-12);
+        let checkpoints = checkpoints(&[Some(gap(0.3, 0.7)), Some(gap(0.55, 0.45)), Some(gap(0.2, 0.9)), Some(gap(0.9, 0.2))]);
+        assert_eq!(kept(policy(0, 0, 1), &checkpoints), [1, 3]);
+    }
+
+    #[test]
+    fn keep_last_off_still_applies_other_rules() {
+        let checkpoints = checkpoints(&[Some(0.3), Some(0.1), Some(0.2), Some(0.4), Some(0.5), Some(0.6)]);
+        assert_eq!(kept(policy(0, 4, 1), &checkpoints), [1, 3, 5]);
+    }
+
+    #[test]
+    fn every_rule_off_keeps_everything() {
+        let checkpoints = checkpoints(&[Some(0.3); 4]);
+        assert_eq!(kept(policy(0, 0, 0), &checkpoints), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn retain_deletes_dropped_checkpoints_and_points_latest_at_the_newest() {
+        let dir = test_dir("retain");
+        let artifact_dir = dir.to_str().unwrap();
+        let config = policy(2, 0, 0);
+        for step in [10, 20, 30] {
+            write_checkpoint(artifact_dir, step);
+            config.retain(artifact_dir, &checkpoint_tag(step), step, None);
+        }
+
+        let mut files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        files.sort();
+        let manifest = CheckpointManifest::load(manifest_path(artifact_dir)).unwrap();
+        let latest = latest_checkpoint(artifact_dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(manifest.latest, "30");
+        assert_eq!(manifest.written, 3);
+        assert_eq!(manifest.checkpoints.iter().map(|entry| entry.tag.as_str()).collect::<Vec<_>>(), ["20", "30"]);
+        assert_eq!(latest.as_deref(), Some("30"));
+        assert!(files.iter().all(|name| !name.contains("-10.")), "checkpoint 10 should be deleted: {files:?}");
+        assert_eq!(files.iter().filter(|name| name.contains("-20.")).count(), 6);
+        assert_eq!(files.iter().filter(|name| name.contains("-30.")).count(), 6);
+    }
+
+    #[test]
+    fn latest_checkpoint_is_newest_even_when_best_is_older() {
+        let dir = test_dir("latest");
+        let artifact_dir = dir.to_str().unwrap();
+        let config = policy(1, 0, 1);
+        for (step, metric) in [(10, 0.1), (20, 0.5), (30, 0.9)] {
+            write_checkpoint(artifact_dir, step);
+            config.retain(artifact_dir, &checkpoint_tag(step), step, Some(metric));
+        }
+        let kept: Vec<_> = CheckpointManifest::load(manifest_path(artifact_dir)).unwrap().checkpoints.into_iter().map(|entry| entry.tag).collect();
+        let latest = latest_checkpoint(artifact_dir);
+        // Without the manifest the checkpoint with the highest step is picked.
+        fs::remove_file(manifest_path(artifact_dir)).unwrap();
+        let latest_without_manifest = latest_checkpoint(artifact_dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(kept, ["10", "30"]);
+        assert_eq!(latest.as_deref(), Some("30"));
+        assert_eq!(latest_without_manifest.as_deref(), Some("30"));
+    }
+
+    #[test]
+    fn training_state_round_trips_through_its_file() {
+        let dir = test_dir("state");
+        let artifact_dir = dir.to_str().unwrap();
+        let config = TrainingConfig::new(GeneratorConfig::new(), DiscriminatorConfig::new()).with_num_epochs(9);
+        let state = TrainingState::new(config, 3, 17, 1234, 1e-4, 2e-4)
+            .with_running_discriminator(RunningDiscriminator { real: 0.7, fake: 0.2 })
+            .with_augment_probability(0.35)
+            .with_discriminator_steps(1100);
+        state.save(state_path(artifact_dir, &checkpoint_tag(state.global_step))).unwrap();
+        let loaded = load_state(artifact_dir, "1234");
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((loaded.epoch, loaded.iteration, loaded.global_step), (3, 17, 1234));
+        assert_eq!((loaded.generator_learning_rate, loaded.discriminator_learning_rate), (1e-4, 2e-4));
+        assert_eq!((loaded.running_discriminator.real, loaded.running_discriminator.fake), (0.7, 0.2));
+        assert_eq!(loaded.augment_probability, 0.35);
+        assert_eq!(loaded.discriminator_steps, 1100);
+        assert_eq!(loaded.config.to_string(), state.config.to_string());
+    }
+}
@@ -0,0 +1,835 @@
+use burn::config::Config;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::{activation::Activation, augment::{AdaConfig, AugmentConfig}, backend::BackendKind, balance::BalanceConfig, bake::{BakeConfig, Preprocessing}, checkpoint::{CheckpointInterval, CheckpointMetric}, gan_loss::GanLoss, instance_noise::NoiseAnneal, labels::LabelSmoothing, lr_schedule::LrSchedule, models::{GeneratorConfig, DiscriminatorConfig, Norm, SUPPORTED_IMAGE_SIZES}, normalization::Normalization, optimizer::OptimizerKind, training::TrainingConfig};
+
+#[derive(Parser, Debug)]
+#[command(name = "gamma", about = "DCGAN training on baked image datasets")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Bake a directory of images into the sqlite training dataset.
+    Bake(BakeArgs),
+    /// Start a new training run.
+    Train(Box<TrainArgs>),
+    /// Generate images from a trained generator.
+    Sample(SampleArgs),
+    /// Print information about a baked dataset and an artifact directory.
+    Inspect(InspectArgs),
+    /// Continue an interrupted training run, exactly where it stopped if it loads batches with at most one worker.
+    Resume(ResumeArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct BakeArgs {
+    /// Directory containing the source images.
+    #[arg(long)]
+    pub input_dir: String,
+    /// Path of the sqlite database to write.
+    #[arg(long, default_value = "training_data.sqlite")]
+    pub sqlite: String,
+    /// Width and height the images are resized to.
+    #[arg(long, default_value_t = 64, value_parser = parse_image_size)]
+    pub image_size: usize,
+    /// How images are brought to a square before resizing.
+    #[arg(long, value_enum, default_value_t = PreprocessingKind::CenterCrop)]
+    pub preprocessing: PreprocessingKind,
+    /// Fraction of the images held out in the validation split.
+    #[arg(long, default_value_t = 0.0)]
+    pub valid_fraction: f64,
+    /// Seed of the train/validation assignment.
+    #[arg(long, default_value_t = 0)]
+    pub split_seed: u64,
+    /// Number of decoding threads, 0 uses one per core.
+    #[arg(long, default_value_t = 0)]
+    pub threads: usize,
+    /// Continue an interrupted bake from its `<sqlite>.partial` staging directory.
+    ///
+    /// A finished database can not be extended: it is only written once all images are decoded and
+    /// its rows do not record the source files they came from.
+    #[arg(long)]
+    pub resume: bool,
+    /// Replace the database if it already exists.
+    #[arg(long)]
+    pub overwrite: bool,
+    /// Also bake images in subdirectories of the input directory.
+    #[arg(long)]
+    pub recursive: bool,
+    /// Only bake images whose path relative to the input directory matches this glob. Can be repeated.
+    #[arg(long)]
+    pub include: Vec<String>,
+    /// Leave out images whose path relative to the input directory matches this glob. Can be repeated.
+    #[arg(long)]
+    pub exclude: Vec<String>,
+}
+
+impl BakeArgs {
+    pub fn bake_config(&self) -> BakeConfig {
+        BakeConfig::new(self.input_dir.clone(), self.sqlite.clone())
+            .with_image_size(self.image_size)
+            .with_preprocessing(self.preprocessing.preprocessing())
+            .with_valid_fraction(self.valid_fraction)
+            .with_split_seed(self.split_seed)
+            .with_num_threads(self.threads)
+            .with_recursive(self.recursive)
+            .with_include(self.include.clone())
+            .with_exclude(self.exclude.clone())
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreprocessingKind {
+    /// Cut the largest centered square and resize it.
+    CenterCrop,
+    /// Fit the whole image and pad the borders with black.
+    Pad,
+    /// Resize ignoring the aspect ratio.
+    Stretch,
+    /// The standard 178x178 face crop of aligned CelebA images.
+    Celeba,
+}
+
+impl PreprocessingKind {
+    fn preprocessing(self) -> Preprocessing {
+        match self {
+            PreprocessingKind::CenterCrop => Preprocessing::CenterCrop,
+            PreprocessingKind::Pad => Preprocessing::PadToSquare,
+            PreprocessingKind::Stretch => Preprocessing::Stretch,
+            PreprocessingKind::Celeba => Preprocessing::celeba(),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct TrainArgs {
+    /// Path of the baked sqlite dataset.
+    #[arg(long, default_value = "training_data.sqlite")]
+    pub sqlite: String,
+    /// Directory for the config, checkpoints and progress snapshots.
+    #[arg(long, default_value = "./artifacts")]
+    pub artifact_dir: String,
+    /// Start from a saved `TrainingConfig` json instead of the defaults.
+    #[arg(long)]
+    pub config: Option<String>,
+    /// Backend to train on.
+    #[arg(long, value_enum, default_value_t = BackendKind::Wgpu)]
+    pub backend: BackendKind,
+    #[arg(long)]
+    pub num_epochs: Option<usize>,
+    #[arg(long)]
+    pub batch_size: Option<usize>,
+    /// Skip the last batch of every epoch when it is smaller than the batch size.
+    #[arg(long)]
+    pub drop_last: bool,
+    /// Threads loading batches, more than one makes the batch order of a resumed epoch differ from the interrupted run.
+    #[arg(long)]
+    pub num_workers: Option<usize>,
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Learning rate of both networks.
+    #[arg(long)]
+    pub learning_rate: Option<f64>,
+    /// Optimizer of the generator.
+    #[arg(long, value_enum)]
+    pub generator_optimizer: Option<OptimizerArg>,
+    /// Optimizer of the discriminator.
+    #[arg(long, value_enum)]
+    pub discriminator_optimizer: Option<OptimizerArg>,
+    /// Learning rate of the generator, overrides `--learning-rate`.
+    #[arg(long)]
+    pub generator_lr: Option<f64>,
+    /// Learning rate of the discriminator, overrides `--learning-rate`.
+    #[arg(long)]
+    pub discriminator_lr: Option<f64>,
+    /// Decay of the first moment estimate of the generator optimizer with Adam and AdamW.
+    #[arg(long)]
+    pub generator_beta1: Option<f32>,
+    /// Decay of the second moment estimate of the generator optimizer with Adam and AdamW.
+    #[arg(long)]
+    pub generator_beta2: Option<f32>,
+    /// Decay of the first moment estimate of the discriminator optimizer with Adam and AdamW.
+    #[arg(long)]
+    pub discriminator_beta1: Option<f32>,
+    /// Decay of the second moment estimate of the discriminator optimizer with Adam and AdamW.
+    #[arg(long)]
+    pub discriminator_beta2: Option<f32>,
+    /// Learning rate schedule of both networks: `constant`, `warmup:<steps>`, `step:<epochs>:<gamma>`,
+    /// `cosine[:<min lr>]` or `linear-decay:<last epochs>`.
+    #[arg(long, value_parser = parse_lr_schedule)]
+    pub lr_schedule: Option<LrSchedule>,
+    /// Learning rate schedule of the generator, overrides `--lr-schedule`.
+    #[arg(long, value_parser = parse_lr_schedule)]
+    pub generator_lr_schedule: Option<LrSchedule>,
+    /// Learning rate schedule of the discriminator, overrides `--lr-schedule`.
+    #[arg(long, value_parser = parse_lr_schedule)]
+    pub discriminator_lr_schedule: Option<LrSchedule>,
+    /// Resolution of both networks, has to match the baked dataset.
+    #[arg(long, value_parser = parse_image_size)]
+    pub image_size: Option<usize>,
+    /// Size of the generator's latent vectors.
+    #[arg(long)]
+    pub latent_size: Option<usize>,
+    /// Base width of the generator's feature maps.
+    #[arg(long)]
+    pub generator_features: Option<usize>,
+    /// Base width of the discriminator's feature maps.
+    #[arg(long)]
+    pub discriminator_features: Option<usize>,
+    /// Normalization of the generator's hidden layers.
+    #[arg(long, value_enum)]
+    pub generator_norm: Option<NormKind>,
+    /// Normalization of the discriminator's hidden layers.
+    #[arg(long, value_enum)]
+    pub discriminator_norm: Option<NormKind>,
+    /// Activation of the generator's hidden layers.
+    #[arg(long, value_enum)]
+    pub generator_activation: Option<ActivationKind>,
+    /// Activation of the discriminator's hidden layers.
+    #[arg(long, value_enum)]
+    pub discriminator_activation: Option<ActivationKind>,
+    /// Adversarial loss both networks are trained with.
+    #[arg(long, value_enum)]
+    pub loss: Option<LossKind>,
+    /// Weight of the WGAN-GP gradient penalty, 0 turns it off. Needs `--discriminator-norm none` or `spectral`.
+    #[arg(long)]
+    pub gradient_penalty_weight: Option<f64>,
+    /// Discriminator updates per generator update.
+    #[arg(long)]
+    pub critic_iterations: Option<usize>,
+    /// Skip or repeat discriminator updates depending on how far ahead it is, with the default thresholds.
+    #[arg(long)]
+    pub adaptive_balance: bool,
+    /// Normalize the pixels with the per-channel mean and std of the training split instead of mapping them to [-1, 1].
+    #[arg(long)]
+    pub dataset_normalization: bool,
+    /// Augment the discriminator inputs with translation, cutout and color (DiffAugment).
+    #[arg(long)]
+    pub augment: bool,
+    /// Also flip the augmented images horizontally.
+    #[arg(long)]
+    pub augment_flip: bool,
+    /// Tune the augmentation probability from discriminator overfitting, starting at 0 (ADA).
+    #[arg(long)]
+    pub ada: bool,
+    /// Smoothing of the discriminator targets, with the default bounds 0.8 and 0.2.
+    #[arg(long, value_enum)]
+    pub label_smoothing: Option<SmoothingKind>,
+    /// Probability of swapping a discriminator target for the one of the other kind.
+    #[arg(long)]
+    pub label_flip_probability: Option<f64>,
+    /// Initial standard deviation of the instance noise, 0 turns it off.
+    #[arg(long)]
+    pub instance_noise_std: Option<f64>,
+    /// How the instance noise fades out: `constant`, `linear:<steps>` or `cosine:<steps>`.
+    #[arg(long, value_parser = parse_noise_anneal)]
+    pub instance_noise_anneal: Option<NoiseAnneal>,
+    /// Weight of the R1 penalty on real images, 0 turns it off. Needs `--discriminator-norm none` or `spectral`.
+    ///
+    /// The gradient norm is estimated by a central difference along the detached input gradient, see `input_gradient_norm`.
+    #[arg(long)]
+    pub r1_weight: Option<f64>,
+    /// Weight of the R2 penalty on generated images, 0 turns it off. Needs `--discriminator-norm none` or `spectral`.
+    ///
+    /// The gradient norm is estimated like for `--r1-weight`.
+    #[arg(long)]
+    pub r2_weight: Option<f64>,
+    /// Discriminator updates between two evaluations of the R1 and R2 penalties.
+    #[arg(long)]
+    pub regularization_interval: Option<usize>,
+    /// Steps between two progress snapshots, 0 turns them off.
+    #[arg(long)]
+    pub snapshot_interval: Option<usize>,
+    /// Side of the square grid of every progress snapshot.
+    #[arg(long)]
+    pub snapshot_grid: Option<usize>,
+    /// Do not assemble the snapshots into a timelapse GIF at the end.
+    #[arg(long)]
+    pub no_timelapse: bool,
+    /// When checkpoints are written: `steps:<steps>` or `minutes:<minutes>`.
+    #[arg(long, value_parser = parse_checkpoint_interval)]
+    pub checkpoint_interval: Option<CheckpointInterval>,
+    /// Number of most recent checkpoints to keep, 0 turns this off. With every `--keep-*` rule off all checkpoints are kept.
+    #[arg(long)]
+    pub keep_last: Option<usize>,
+    /// Also keep every n-th checkpoint, 0 turns this off.
+    #[arg(long)]
+    pub keep_every: Option<usize>,
+    /// Also keep the n checkpoints with the lowest `--checkpoint-metric`, 0 turns this off.
+    #[arg(long)]
+    pub keep_best: Option<usize>,
+    /// Metric the checkpoints are ranked by for `--keep-best`, averaged since the previous checkpoint.
+    #[arg(long, value_enum)]
+    pub checkpoint_metric: Option<MetricKind>,
+    /// Decay of the moving average of the generator weights.
+    #[arg(long)]
+    pub ema_decay: Option<f64>,
+    /// Steps during which the moving average just copies the generator.
+    #[arg(long)]
+    pub ema_warmup_steps: Option<usize>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormKind {
+    /// Batch norm after every hidden convolution.
+    Batch,
+    /// Spectral normalization of every layer's weights.
+    Spectral,
+    None,
+}
+
+impl NormKind {
+    fn norm(self) -> Norm {
+        match self {
+            NormKind::Batch => Norm::Batch,
+            NormKind::Spectral => Norm::Spectral,
+            NormKind::None => Norm::None,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActivationKind {
+    /// LeakyReLU with a negative slope of 0.2.
+    LeakyRelu,
+    Relu,
+    Gelu,
+    Silu,
+    /// ELU with alpha 1.
+    Elu,
+}
+
+impl ActivationKind {
+    fn activation(self) -> Activation {
+        match self {
+            ActivationKind::LeakyRelu => Activation::leaky_relu(),
+            ActivationKind::Relu => Activation::Relu,
+            ActivationKind::Gelu => Activation::Gelu,
+            ActivationKind::Silu => Activation::Silu,
+            ActivationKind::Elu => Activation::Elu { alpha: 1.0 },
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LossKind {
+    /// Binary cross entropy with the non-saturating generator loss.
+    NonSaturating,
+    Hinge,
+    /// LSGAN.
+    LeastSquares,
+    /// WGAN.
+    Wasserstein,
+    /// Relativistic average GAN.
+    RelativisticAverage,
+}
+
+impl LossKind {
+    fn loss(self) -> GanLoss {
+        match self {
+            LossKind::NonSaturating => GanLoss::NonSaturating,
+            LossKind::Hinge => GanLoss::Hinge,
+            LossKind::LeastSquares => GanLoss::LeastSquares,
+            LossKind::Wasserstein => GanLoss::Wasserstein,
+            LossKind::RelativisticAverage => GanLoss::RelativisticAverage,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptimizerArg {
+    Adam,
+    #[value(name = "adamw")]
+    AdamW,
+    /// SGD, with the momentum of the config file.
+    Sgd,
+    #[value(name = "rmsprop")]
+    RmsProp,
+}
+
+impl OptimizerArg {
+    fn kind(self) -> OptimizerKind {
+        match self {
+            OptimizerArg::Adam => OptimizerKind::Adam,
+            OptimizerArg::AdamW => OptimizerKind::AdamW,
+            OptimizerArg::Sgd => OptimizerKind::Sgd,
+            OptimizerArg::RmsProp => OptimizerKind::RmsProp,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmoothingKind {
+    None,
+    /// Only the real targets are smoothed.
+    OneSided,
+    TwoSided,
+}
+
+impl SmoothingKind {
+    fn smoothing(self) -> LabelSmoothing {
+        match self {
+            SmoothingKind::None => LabelSmoothing::None,
+            SmoothingKind::OneSided => LabelSmoothing::OneSided { real_min: 0.8 },
+            SmoothingKind::TwoSided => LabelSmoothing::TwoSided { real_min: 0.8, fake_max: 0.2 },
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    GeneratorLoss,
+    DiscriminatorLoss,
+    /// |D(x) - D(G(z))|.
+    DiscriminatorGap,
+}
+
+impl MetricKind {
+    fn metric(self) -> CheckpointMetric {
+        match self {
+            MetricKind::GeneratorLoss => CheckpointMetric::GeneratorLoss,
+            MetricKind::DiscriminatorLoss => CheckpointMetric::DiscriminatorLoss,
+            MetricKind::DiscriminatorGap => CheckpointMetric::DiscriminatorGap,
+        }
+    }
+}
+
+impl TrainArgs {
+    /// Loads the base config (file or defaults) and applies the command line overrides on top.
+    ///
+    /// `dataset_normalization` is the normalization computed from the dataset for `--dataset-normalization`.
+    pub fn training_config(&self, dataset_normalization: Option<Normalization>) -> TrainingConfig {
+        let mut config = match &self.config {
+            Some(path) => TrainingConfig::load(path).expect("Training config should be readable"),
+            None => TrainingConfig::new(GeneratorConfig::new(), DiscriminatorConfig::new()),
+        };
+        if let Some(num_epochs) = self.num_epochs {
+            config.num_epochs = num_epochs;
+        }
+        if let Some(batch_size) = self.batch_size {
+            config.batch_size = batch_size;
+        }
+        config.drop_last |= self.drop_last;
+        if let Some(num_workers) = self.num_workers {
+            config.num_workers = num_workers;
+        }
+        if let Some(seed) = self.seed {
+            config.seed = seed;
+        }
+        if let Some(learning_rate) = self.learning_rate {
+            config.generator_optimizer.learning_rate = learning_rate;
+            config.discriminator_optimizer.learning_rate = learning_rate;
+        }
+        if let Some(generator_optimizer) = self.generator_optimizer {
+            config.generator_optimizer.kind = generator_optimizer.kind();
+        }
+        if let Some(discriminator_optimizer) = self.discriminator_optimizer {
+            config.discriminator_optimizer.kind = discriminator_optimizer.kind();
+        }
+        if let Some(lr_schedule) = &self.lr_schedule {
+            config.generator_optimizer.schedule = lr_schedule.clone();
+            config.discriminator_optimizer.schedule = lr_schedule.clone();
+        }
+        if let Some(generator_lr_schedule) = &self.generator_lr_schedule {
+            config.generator_optimizer.schedule = generator_lr_schedule.clone();
+        }
+        if let Some(discriminator_lr_schedule) = &self.discriminator_lr_schedule {
+            config.discriminator_optimizer.schedule = discriminator_lr_schedule.clone();
+        }
+        if let Some(generator_lr) = self.generator_lr {
+            config.generator_optimizer.learning_rate = generator_lr;
+        }
+        if let Some(discriminator_lr) = self.discriminator_lr {
+            config.discriminator_optimizer.learning_rate = discriminator_lr;
+        }
+        if let Some(generator_beta1) = self.generator_beta1 {
+            config.generator_optimizer.beta_1 = generator_beta1;
+        }
+        if let Some(generator_beta2) = self.generator_beta2 {
+            config.generator_optimizer.beta_2 = generator_beta2;
+        }
+        if let Some(discriminator_beta1) = self.discriminator_beta1 {
+            config.discriminator_optimizer.beta_1 = discriminator_beta1;
+        }
+        if let Some(discriminator_beta2) = self.discriminator_beta2 {
+            config.discriminator_optimizer.beta_2 = discriminator_beta2;
+        }
+        if let Some(image_size) = self.image_size {
+            config.generator.image_size = image_size;
+            config.discriminator.image_size = image_size;
+        }
+        if let Some(latent_size) = self.latent_size {
+            config.generator.latent_vector_size = latent_size;
+        }
+        if let Some(generator_features) = self.generator_features {
+            config.generator.feature_map_size = generator_features;
+        }
+        if let Some(discriminator_features) = self.discriminator_features {
+            config.discriminator.feature_map_size = discriminator_features;
+        }
+        if let Some(generator_norm) = self.generator_norm {
+            config.generator.norm = generator_norm.norm();
+        }
+        if let Some(discriminator_norm) = self.discriminator_norm {
+            config.discriminator.norm = discriminator_norm.norm();
+        }
+        if let Some(generator_activation) = self.generator_activation {
+            config.generator.activation = generator_activation.activation();
+        }
+        if let Some(discriminator_activation) = self.discriminator_activation {
+            config.discriminator.activation = discriminator_activation.activation();
+        }
+        if let Some(loss) = self.loss {
+            config.loss = loss.loss();
+        }
+        if let Some(gradient_penalty_weight) = self.gradient_penalty_weight {
+            config.gradient_penalty_weight = gradient_penalty_weight;
+        }
+        if let Some(critic_iterations) = self.critic_iterations {
+            config.critic_iterations = critic_iterations;
+        }
+        if self.adaptive_balance && config.balance.is_none() {
+            config.balance = Some(BalanceConfig::new());
+        }
+        if let Some(normalization) = dataset_normalization {
+            config.normalization = normalization;
+        }
+        if (self.augment || self.augment_flip || self.ada) && config.augment.is_none() {
+            config.augment = Some(AugmentConfig::new());
+        }
+        if let Some(augment) = &mut config.augment {
+            augment.flip |= self.augment_flip;
+            if self.ada && augment.ada.is_none() {
+                augment.probability = 0.0;
+                augment.ada = Some(AdaConfig::new());
+            }
+        }
+        if let Some(label_smoothing) = self.label_smoothing {
+            config.labels.smoothing = label_smoothing.smoothing();
+        }
+        if let Some(label_flip_probability) = self.label_flip_probability {
+            config.labels.flip_probability = label_flip_probability;
+        }
+        if let Some(instance_noise_std) = self.instance_noise_std {
+            config.instance_noise.std = instance_noise_std;
+        }
+        if let Some(instance_noise_anneal) = &self.instance_noise_anneal {
+            config.instance_noise.anneal = instance_noise_anneal.clone();
+        }
+        if let Some(r1_weight) = self.r1_weight {
+            config.r1_weight = r1_weight;
+        }
+        if let Some(r2_weight) = self.r2_weight {
+            config.r2_weight = r2_weight;
+        }
+        if let Some(regularization_interval) = self.regularization_interval {
+            config.regularization_interval = regularization_interval;
+        }
+        if let Some(snapshot_interval) = self.snapshot_interval {
+            config.snapshot.interval = snapshot_interval;
+        }
+        if let Some(snapshot_grid) = self.snapshot_grid {
+            config.snapshot.grid_size = snapshot_grid;
+        }
+        if self.no_timelapse {
+            config.snapshot.timelapse = false;
+        }
+        if let Some(interval) = &self.checkpoint_interval {
+            config.checkpoint.interval = interval.clone();
+        }
+        if let Some(keep_last) = self.keep_last {
+            config.checkpoint.keep_last = keep_last;
+        }
+        if let Some(keep_every) = self.keep_every {
+            config.checkpoint.keep_every = keep_every;
+        }
+        if let Some(keep_best) = self.keep_best {
+            config.checkpoint.keep_best = keep_best;
+        }
+        if let Some(metric) = self.checkpoint_metric {
+            config.checkpoint.metric = metric.metric();
+        }
+        if let Some(ema_decay) = self.ema_decay {
+            config.ema_decay = ema_decay;
+        }
+        if let Some(ema_warmup_steps) = self.ema_warmup_steps {
+            config.ema_warmup_steps = ema_warmup_steps;
+        }
+        config
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SampleArgs {
+    /// Artifact directory of the training run to sample from.
+    #[arg(long, default_value = "./artifacts")]
+    pub artifact_dir: String,
+    /// Checkpoint to load the generator from, by its global step. Defaults to the latest one.
+    #[arg(long)]
+    pub checkpoint: Option<String>,
+    /// Number of individual images to write.
+    #[arg(long, default_value_t = 16)]
+    pub count: usize,
+    /// Write a single contact sheet of `<rows>x<columns>` images instead of individual images.
+    #[arg(long, value_parser = parse_grid)]
+    pub grid: Option<(usize, usize)>,
+    /// Seed for drawing the latent vectors.
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Directory to write the images to. Defaults to `<artifact-dir>/samples`.
+    #[arg(long)]
+    pub output_dir: Option<String>,
+    /// Sample from the live generator instead of its moving average.
+    #[arg(long)]
+    pub no_ema: bool,
+    /// Backend to run the generator on.
+    #[arg(long, value_enum, default_value_t = BackendKind::Wgpu)]
+    pub backend: BackendKind,
+}
+
+fn parse_image_size(value: &str) -> Result<usize, String> {
+    let image_size = value.parse().map_err(|err| format!("invalid image size: {err}"))?;
+    if !SUPPORTED_IMAGE_SIZES.contains(&image_size) {
+        return Err(format!("image size has to be one of {SUPPORTED_IMAGE_SIZES:?}"));
+    }
+    Ok(image_size)
+}
+
+fn parse_lr_schedule(value: &str) -> Result<LrSchedule, String> {
+    let mut parts = value.split(':');
+    let kind = parts.next().unwrap_or_default();
+    let arguments: Vec<&str> = parts.collect();
+    let max_arguments = match kind {
+        "constant" => 0,
+        "warmup" | "cosine" | "linear-decay" => 1,
+        "step" => 2,
+        _ => return Err(format!("unknown schedule {kind}, expected constant, warmup, step, cosine or linear-decay")),
+    };
+    if arguments.len() > max_arguments {
+        return Err(format!("{kind} schedule takes at most {max_arguments} arguments, got {}", arguments.len()));
+    }
+    let argument = |index: usize| -> Result<&str, String> { arguments.get(index).copied().ok_or_else(|| format!("{kind} schedule is missing argument {}", index + 1)) };
+    let invalid = |err: &dyn std::fmt::Display| format!("invalid {kind} schedule argument: {err}");
+    let number = |index: usize| -> Result<f64, String> { argument(index)?.parse().map_err(|err| invalid(&err)) };
+    let count = |index: usize| -> Result<usize, String> { argument(index)?.parse().map_err(|err| invalid(&err)) };
+    match kind {
+        "constant" => Ok(LrSchedule::Constant),
+        "warmup" => Ok(LrSchedule::Warmup { steps: count(0)? }),
+        "step" => Ok(LrSchedule::StepDecay { epochs: count(0)?, gamma: number(1)? }),
+        "cosine" => Ok(LrSchedule::Cosine { min_learning_rate: if arguments.is_empty() { 0.0 } else { number(0)? } }),
+        "linear-decay" => Ok(LrSchedule::LinearDecay { epochs: count(0)? }),
+        _ => unreachable!("unknown schedules are rejected with their arguments"),
+    }
+}
+
+fn parse_noise_anneal(value: &str) -> Result<NoiseAnneal, String> {
+    let (kind, steps) = value.split_once(':').unwrap_or((value, ""));
+    let steps = || steps.parse().map_err(|err| format!("invalid number of {kind} annealing steps: {err}"));
+    match kind {
+        "constant" => Ok(NoiseAnneal::Constant),
+        "linear" => Ok(NoiseAnneal::Linear { steps: steps()? }),
+        "cosine" => Ok(NoiseAnneal::Cosine { steps: steps()? }),
+        _ => Err(format!("unknown annealing {kind}, expected constant, linear or cosine")),
+    }
+}
+
+fn parse_checkpoint_interval(value: &str) -> Result<CheckpointInterval, String> {
+    let (kind, amount) = value.split_once(':').ok_or_else(|| format!("expected steps:<steps> or minutes:<minutes>, got {value}"))?;
+    match kind {
+        "steps" => Ok(CheckpointInterval::Steps { steps: amount.parse().map_err(|err| format!("invalid number of steps: {err}"))? }),
+        "minutes" => {
+            let minutes: f64 = amount.parse().map_err(|err| format!("invalid number of minutes: {err}"))?;
+            if !(minutes.is_finite() && minutes > 0.0) {
+                return Err(format!("checkpoint interval is {minutes} minutes, expected a positive value"));
+            }
+            Ok(CheckpointInterval::Minutes { minutes })
+        }
+        _ => Err(format!("unknown checkpoint interval {kind}, expected steps or minutes")),
+    }
+}
+
+fn parse_grid(value: &str) -> Result<(usize, usize), String> {
+    let (rows, columns) = value
+        .split_once('x')
+        .ok_or_else(|| format!("expected <rows>x<columns>, got {value}"))?;
+    let rows = rows.parse().map_err(|err| format!("invalid number of rows: {err}"))?;
+    let columns = columns.parse().map_err(|err| format!("invalid number of columns: {err}"))?;
+    if rows == 0 || columns == 0 {
+        return Err("the grid needs at least one row and one column".to_string());
+    }
+    Ok((rows, columns))
+}
+
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    /// Path of the baked sqlite dataset.
+    #[arg(long, default_value = "training_data.sqlite")]
+    pub sqlite: String,
+    /// Artifact directory of a training run.
+    #[arg(long, default_value = "./artifacts")]
+    pub artifact_dir: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ResumeArgs {
+    /// Path of the baked sqlite dataset.
+    #[arg(long, default_value = "training_data.sqlite")]
+    pub sqlite: String,
+    /// Artifact directory of the run to continue.
+    #[arg(long, default_value = "./artifacts")]
+    pub artifact_dir: String,
+    /// Checkpoint to continue from, by its global step. Defaults to the latest one.
+    #[arg(long)]
+    pub checkpoint: Option<String>,
+    /// Backend to train on.
+    #[arg(long, value_enum, default_value_t = BackendKind::Wgpu)]
+    pub backend: BackendKind,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn train_args(args: &[&str]) -> TrainArgs {
+        match Cli::try_parse_from(["gamma", "train"].iter().chain(args)).unwrap().command {
+            Command::Train(args) => *args,
+            command => panic!("expected the train command, got {command:?}"),
+        }
+    }
+
+    /// Saves `config` as the `--config` file of a test and returns its path.
+    fn config_file(name: &str, config: &TrainingConfig) -> String {
+        let path = std::env::temp_dir().join(format!("gamma-{name}-{}.json", std::process::id()));
+        config.save(&path).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn parse_grid_accepts_rows_by_columns() {
+        assert_eq!(parse_grid("4x8"), Ok((4, 8)));
+        assert_eq!(parse_grid("1x1"), Ok((1, 1)));
+    }
+
+    #[test]
+    fn parse_grid_rejects_malformed_and_empty_grids() {
+        for value in ["4", "4x", "x8", "4x8x2", "ax8", "-1x8", "0x8", "4x0"] {
+            assert!(parse_grid(value).is_err(), "{value} should be rejected");
+        }
+    }
+
+    #[test]
+    fn parse_image_size_accepts_only_supported_sizes() {
+        for size in SUPPORTED_IMAGE_SIZES {
+            assert_eq!(parse_image_size(&size.to_string()), Ok(size));
+        }
+        for value in ["48", "0", "512", "sixty-four", ""] {
+            assert!(parse_image_size(value).is_err(), "{value} should be rejected");
+        }
+    }
+
+    #[test]
+    fn parse_lr_schedule_accepts_every_schedule() {
+        assert!(matches!(parse_lr_schedule("constant"), Ok(LrSchedule::Constant)));
+        assert!(matches!(parse_lr_schedule("warmup:500"), Ok(LrSchedule::Warmup { steps: 500 })));
+        assert!(matches!(parse_lr_schedule("step:10:0.5"), Ok(LrSchedule::StepDecay { epochs: 10, gamma }) if gamma == 0.5));
+        assert!(matches!(parse_lr_schedule("cosine"), Ok(LrSchedule::Cosine { min_learning_rate }) if min_learning_rate == 0.0));
+        assert!(matches!(parse_lr_schedule("cosine:1e-6"), Ok(LrSchedule::Cosine { min_learning_rate }) if min_learning_rate == 1e-6));
+        assert!(matches!(parse_lr_schedule("linear-decay:3"), Ok(LrSchedule::LinearDecay { epochs: 3 })));
+    }
+
+    #[test]
+    fn parse_lr_schedule_rejects_unknown_and_malformed_schedules() {
+        for value in ["", "exponential", "exponential:1:2", "warmup", "warmup:-1", "warmup:ten", "step:10", "step:10:half", "cosine:low", "linear-decay", "constant:x", "constant:", "warmup:5:9", "step:10:0.5:1", "cosine:1e-5:junk", "linear-decay:3:1"] {
+            assert!(parse_lr_schedule(value).is_err(), "{value} should be rejected");
+        }
+    }
+
+    #[test]
+    fn parse_noise_anneal_accepts_every_annealing() {
+        assert!(matches!(parse_noise_anneal("constant"), Ok(NoiseAnneal::Constant)));
+        assert!(matches!(parse_noise_anneal("linear:1000"), Ok(NoiseAnneal::Linear { steps: 1000 })));
+        assert!(matches!(parse_noise_anneal("cosine:20"), Ok(NoiseAnneal::Cosine { steps: 20 })));
+    }
+
+    #[test]
+    fn parse_noise_anneal_rejects_unknown_and_malformed_annealing() {
+        for value in ["", "exponential:10", "linear", "linear:-5", "cosine:many"] {
+            assert!(parse_noise_anneal(value).is_err(), "{value} should be rejected");
+        }
+    }
+
+    #[test]
+    fn parse_checkpoint_interval_accepts_steps_and_minutes() {
+        assert!(matches!(parse_checkpoint_interval("steps:500"), Ok(CheckpointInterval::Steps { steps: 500 })));
+        assert!(matches!(parse_checkpoint_interval("minutes:2.5"), Ok(CheckpointInterval::Minutes { minutes }) if minutes == 2.5));
+    }
+
+    #[test]
+    fn parse_checkpoint_interval_rejects_unknown_and_malformed_intervals() {
+        for value in ["", "500", "steps", "steps:", "steps:1.5", "minutes:soon", "minutes:0", "minutes:-1", "minutes:NaN", "minutes:nan", "minutes:inf", "minutes:-inf", "epochs:1"] {
+            assert!(parse_checkpoint_interval(value).is_err(), "{value} should be rejected");
+        }
+    }
+
+    #[test]
+    fn overrides_apply_on_top_of_config_file() {
+        let mut file_config = TrainingConfig::new(GeneratorConfig::new(), DiscriminatorConfig::new())
+            .with_num_epochs(7)
+            .with_batch_size(16)
+            .with_loss(GanLoss::Hinge)
+            .with_drop_last(true);
+        file_config.generator_optimizer.learning_rate = 0.001;
+        let path = config_file("overrides", &file_config);
+        let config = train_args(&["--config", &path, "--batch-size", "32", "--discriminator-lr", "0.003"]).training_config(None);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!((config.num_epochs, config.batch_size), (7, 32));
+        assert!(matches!(config.loss, GanLoss::Hinge));
+        assert!(config.drop_last, "an unset flag should not turn off drop_last of the file");
+        assert_eq!(config.generator_optimizer.learning_rate, 0.001);
+        assert_eq!(config.discriminator_optimizer.learning_rate, 0.003);
+    }
+
+    #[test]
+    fn per_network_overrides_win_over_shared_ones() {
+        let config = train_args(&["--learning-rate", "0.01", "--discriminator-lr", "0.02", "--lr-schedule", "cosine", "--generator-lr-schedule", "warmup:10"]).training_config(None);
+        assert_eq!(config.generator_optimizer.learning_rate, 0.01);
+        assert_eq!(config.discriminator_optimizer.learning_rate, 0.02);
+        assert!(matches!(config.generator_optimizer.schedule, LrSchedule::Warmup { steps: 10 }));
+        assert!(matches!(config.discriminator_optimizer.schedule, LrSchedule::Cosine { .. }));
+    }
+
+    #[test]
+    fn beta_flags_set_the_optimizer_of_their_network() {
+        let config = train_args(&["--discriminator-optimizer", "adam", "--discriminator-beta1", "0.0", "--discriminator-beta2", "0.99", "--generator-beta2", "0.9"]).training_config(None);
+        assert_eq!((config.generator_optimizer.beta_1, config.generator_optimizer.beta_2), (0.5, 0.9));
+        assert_eq!((config.discriminator_optimizer.beta_1, config.discriminator_optimizer.beta_2), (0.0, 0.99));
+    }
+
+    #[test]
+    fn section_flags_extend_sections_of_config_file() {
+        let file_config = TrainingConfig::new(GeneratorConfig::new(), DiscriminatorConfig::new())
+            .with_augment(Some(AugmentConfig::new().with_probability(0.3)));
+        let path = config_file("sections", &file_config);
+        let config = train_args(&["--config", &path, "--augment", "--augment-flip"]).training_config(None);
+        std::fs::remove_file(path).unwrap();
+        let augment = config.augment.unwrap();
+        assert_eq!(augment.probability, 0.3);
+        assert!(augment.flip && augment.ada.is_none());
+
+        let augment = train_args(&["--ada"]).training_config(None).augment.unwrap();
+        assert_eq!(augment.probability, 0.0);
+        assert!(augment.ada.is_some());
+    }
+
+    #[test]
+    fn dataset_normalization_replaces_configured_one() {
+        let args = train_args(&["--dataset-normalization"]);
+        let normalization = Normalization::new().with_mean([0.1, 0.2, 0.3]).with_std([0.4, 0.5, 0.6]);
+        let config = args.training_config(Some(normalization));
+        assert_eq!((config.normalization.mean, config.normalization.std), ([0.1, 0.2, 0.3], [0.4, 0.5, 0.6]));
+        assert_eq!(train_args(&[]).training_config(None).normalization.mean, [0.5; 3]);
+    }
+}
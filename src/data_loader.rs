@@ -1,23 +1,26 @@
-use std::{fs, ffi::OsStr};
-
-use burn::{data::dataset::{SqliteDatasetWriter, SqliteDataset}, tensor::DataSerialize};
-use image::{GenericImageView, Rgb};
-use image::io::Reader as ImageReader;
+use burn::{data::dataset::SqliteDataset, tensor::DataSerialize};
 
 use burn::{
-    data::{dataloader::batcher::Batcher, dataset::source::huggingface::MNISTItem},
-    tensor::{backend::{Backend, AutodiffBackend}, Data, ElementConversion, Int, Tensor}, train::{TrainStep, ClassificationOutput, TrainOutput, ValidStep},
+    data::dataloader::batcher::Batcher,
+    tensor::{backend::Backend, Data, Tensor},
 };
 
-use crate::image::{IMAGE_HEIGHT, IMAGE_WIDTH};
+use crate::normalization::Normalization;
+
+/// Split holding the training images.
+pub const TRAIN_SPLIT: &str = "train";
+/// Split holding the images held out from training.
+pub const VALID_SPLIT: &str = "valid";
 
 pub struct ImageBatcher<B: Backend> {
     device: B::Device,
+    image_size: usize,
+    normalization: Normalization,
 }
 
 impl<B: Backend> ImageBatcher<B> {
-    pub fn new(device: B::Device) -> Self {
-        Self { device }
+    pub fn new(device: B::Device, image_size: usize, normalization: Normalization) -> Self {
+        Self { device, image_size, normalization }
     }
 }
 
@@ -31,14 +34,16 @@ impl<B: Backend> Batcher<DataSerialize<u8>, ImageBatch<B>> for ImageBatcher<B> {
     fn batch(&self, items: Vec<DataSerialize<u8>>) -> ImageBatch<B> {
         let images = items
             .iter()
+            .inspect(|data| assert_eq!(data.shape, [self.image_size, self.image_size, 3], "Dataset images do not match the configured image size {}, bake the dataset with the same size", self.image_size))
             .map(|data| data.into())
             .map(|data: Data<u8, 3>| data.clone().convert())
             .map(|data: Data<f32, 3>| Tensor::<B, 3>::from_data(data.convert()))
-            .map(|tensor| tensor.reshape([1, 3, IMAGE_WIDTH, IMAGE_HEIGHT]))
-            .map(|tensor| (tensor / 255) - 0.5)
+            // Baked pixels are [height, width, channels], the networks want [channels, height, width].
+            .map(|tensor| tensor.swap_dims(1, 2).swap_dims(0, 1).reshape([1, 3, self.image_size, self.image_size]))
+            .map(|tensor| tensor / 255)
             .collect();
 
-        let images = Tensor::cat(images, 0).to_device(&self.device);
+        let images = self.normalization.encode(Tensor::cat(images, 0).to_device(&self.device));
 
         ImageBatch { images }
     }
@@ -46,35 +51,7 @@ impl<B: Backend> Batcher<DataSerialize<u8>, ImageBatch<B>> for ImageBatcher<B> {
 
 
 
-pub fn make_image_dataset() -> SqliteDataset<DataSerialize<u8>>{
-    SqliteDataset::from_db_file("training_data.sqlite", "train").unwrap()
+/// Opens split `split` of a baked dataset, `None` if the database has no such split.
+pub fn make_image_dataset(db_file: &str, split: &str) -> Option<SqliteDataset<DataSerialize<u8>>>{
+    SqliteDataset::from_db_file(db_file, split).ok()
 }
-
-
-pub fn bake_image_dataset(path: &str) {
-    let mut writer: SqliteDatasetWriter<DataSerialize<u8>> = SqliteDatasetWriter::new("training_data.sqlite", false).unwrap();
-    let paths = fs::read_dir(path).unwrap();
-    #[allow(clippy::never_loop)]
-    for path in paths {
-        let path = path.unwrap();
-        if OsStr::to_str(path.path().extension().unwrap()).unwrap() == "jpg" {
-            let img = ImageReader::open(path.path()).unwrap().decode().unwrap();    
-            let img = img.resize(IMAGE_HEIGHT as u32, IMAGE_WIDTH as u32, image::imageops::FilterType::Lanczos3);        
-            let mut img_buf = [[[0 ; 3 ]; IMAGE_HEIGHT] ; IMAGE_WIDTH];
-
-            for (x, y, pixel) in img.pixels() {
-                img_buf[y as usize][x as usize][0] = pixel.0[0];
-                img_buf[y as usize][x as usize][1] = pixel.0[1];
-                img_buf[y as usize][x as usize][2] = pixel.0[2];
-            }
-
-            let data: Data<u8, 3> = img_buf.into();
-            let data = data.serialize();
-
-            // Insert into sqlite
-            writer.write("train", &data).unwrap();
-            println!("Wrote: {:?}", path.path());
-        }
-    }
-    writer.set_completed().unwrap();
-}
\ No newline at end of file
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use burn::{module::{Module, ModuleMapper, ModuleVisitor, ParamId}, tensor::{backend::{AutodiffBackend, Backend}, Tensor}};
+
+use crate::models::Generator;
+
+/// Collects every float tensor of the live generator, flattened and without autodiff.
+struct LiveTensors<B: AutodiffBackend> {
+    tensors: HashMap<ParamId, Tensor<B::InnerBackend, 1>>,
+}
+
+impl<B: AutodiffBackend> ModuleVisitor<B> for LiveTensors<B> {
+    fn visit_float<const D: usize>(&mut self, id: &ParamId, tensor: &Tensor<B, D>) {
+        let num_elements = tensor.shape().num_elements();
+        self.tensors.insert(id.clone(), tensor.clone().inner().reshape([num_elements]));
+    }
+}
+
+/// Moves every tensor of the average towards the live tensor with the same id.
+struct MoveAverage<B: Backend> {
+    live: HashMap<ParamId, Tensor<B, 1>>,
+    decay: f64,
+}
+
+impl<B: Backend> ModuleMapper<B> for MoveAverage<B> {
+    fn map_float<const D: usize>(&mut self, id: &ParamId, tensor: Tensor<B, D>) -> Tensor<B, D> {
+        match self.live.remove(id) {
+            Some(live) => {
+                let shape = tensor.shape();
+                tensor.mul_scalar(self.decay) + live.reshape(shape).mul_scalar(1.0 - self.decay)
+            }
+            None => tensor,
+        }
+    }
+}
+
+/// Decay of the update after `step` steps: 0 during the first `warmup_steps`, so the average copies the generator, then `decay`.
+pub fn warmup_decay(decay: f64, warmup_steps: usize, step: usize) -> f64 {
+    if step < warmup_steps { 0.0 } else { decay }
+}
+
+/// Returns `decay * average + (1 - decay) * generator` for every parameter and running statistic.
+///
+/// The average is created with `generator.valid()`, so both share their parameter ids and the
+/// average lives on the inner backend, where it never takes part in the autodiff graph.
+pub fn update_average<B: AutodiffBackend>(average: Generator<B::InnerBackend>, generator: &Generator<B>, decay: f64) -> Generator<B::InnerBackend> {
+    let mut live = LiveTensors::<B> { tensors: HashMap::new() };
+    generator.visit(&mut live);
+    average.map(&mut MoveAverage { live: live.tensors, decay })
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{backend::{Autodiff, NdArray}, module::AutodiffModule, nn::Initializer};
+
+    use super::*;
+    use crate::models::GeneratorConfig;
+
+    type TestBackend = Autodiff<NdArray<f32>>;
+
+    /// Every float tensor of a module, by id.
+    struct Values(HashMap<ParamId, Vec<f32>>);
+
+    impl<B: Backend> ModuleVisitor<B> for Values {
+        fn visit_float<const D: usize>(&mut self, id: &ParamId, tensor: &Tensor<B, D>) {
+            self.0.insert(id.clone(), tensor.to_data().convert::<f32>().value);
+        }
+    }
+
+    fn values<B: Backend>(generator: &Generator<B>) -> HashMap<ParamId, Vec<f32>> {
+        let mut values = Values(HashMap::new());
+        generator.visit(&mut values);
+        values.0
+    }
+
+    /// Adds a constant to every float tensor, standing in for a training step.
+    struct Shift(f32);
+
+    impl<B: Backend> ModuleMapper<B> for Shift {
+        fn map_float<const D: usize>(&mut self, _id: &ParamId, tensor: Tensor<B, D>) -> Tensor<B, D> {
+            tensor.add_scalar(self.0)
+        }
+    }
+
+    fn assert_values_close(actual: &HashMap<ParamId, Vec<f32>>, expected: &HashMap<ParamId, Vec<f32>>) {
+        assert_eq!(actual.len(), expected.len());
+        for (id, expected) in expected {
+            for (actual, expected) in actual[id].iter().zip(expected) {
+                assert!((actual - expected).abs() < 1e-5, "{actual} != {expected}");
+            }
+        }
+    }
+
+    /// The average of a fresh generator and the generator after every weight moved by 1.
+    fn average_and_trained() -> (Generator<NdArray<f32>>, Generator<TestBackend>) {
+        let generator = GeneratorConfig::new().with_latent_vector_size(4).with_feature_map_size(2).with_image_size(32).init::<TestBackend>(&Initializer::Normal { mean: 0.0, std: 0.02 });
+        let average = generator.valid();
+        (average, generator.map(&mut Shift(1.0)))
+    }
+
+    #[test]
+    fn zero_decay_copies_the_generator() {
+        let (average, generator) = average_and_trained();
+        let updated = update_average(average, &generator, 0.0);
+        assert_values_close(&values(&updated), &values(&generator));
+    }
+
+    #[test]
+    fn unit_decay_keeps_the_average() {
+        let (average, generator) = average_and_trained();
+        let before = values(&average);
+        let updated = update_average(average, &generator, 1.0);
+        assert_values_close(&values(&updated), &before);
+    }
+
+    #[test]
+    fn decay_weighs_average_and_generator() {
+        let (average, generator) = average_and_trained();
+        let expected = values(&average).into_iter().map(|(id, values)| (id, values.into_iter().map(|value| value + 0.75).collect())).collect();
+        let updated = update_average(average, &generator, 0.25);
+        assert_values_close(&values(&updated), &expected);
+    }
+
+    #[test]
+    fn warmup_copies_the_generator_until_it_ends() {
+        assert_eq!(warmup_decay(0.999, 100, 0), 0.0);
+        assert_eq!(warmup_decay(0.999, 100, 99), 0.0);
+        assert_eq!(warmup_decay(0.999, 100, 100), 0.999);
+        assert_eq!(warmup_decay(0.999, 0, 0), 0.999);
+
+        // During the warm-up an update leaves the average at the generator.
+        let (average, generator) = average_and_trained();
+        let updated = update_average(average, &generator, warmup_decay(0.999, 100, 10));
+        assert_values_close(&values(&updated), &values(&generator));
+    }
+}
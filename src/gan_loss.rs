@@ -0,0 +1,134 @@
+use burn::{config::Config, tensor::{backend::Backend, Tensor}};
+
+/// Adversarial objective both networks are trained with, all variants work on raw discriminator logits.
+#[derive(Config, Debug)]
+pub enum GanLoss {
+    /// Binary cross entropy, the generator maximizes `log D(G(z))` instead of minimizing `log(1 - D(G(z)))`.
+    NonSaturating,
+    /// Hinge loss of Geometric GAN / SAGAN.
+    Hinge,
+    /// Squared distance of the logits to the targets (LSGAN).
+    LeastSquares,
+    /// Difference of the mean critic scores (WGAN), needs a Lipschitz constraint on the discriminator.
+    Wasserstein,
+    /// Binary cross entropy on how much more real an image looks than the average of the other kind (RaGAN).
+    RelativisticAverage,
+}
+
+/// Binary cross entropy of `sigmoid(logits)` and `targets`, computed without leaving the log domain.
+///
+/// Uses `max(x, 0) - x * t + log(1 + exp(-|x|))` so saturated logits give large but finite losses.
+pub fn bce_with_logits<B: Backend>(logits: Tensor<B, 1>, targets: Tensor<B, 1>) -> Tensor<B, 1> {
+    let loss = logits.clone().clamp_min(0.0) - logits.clone() * targets + logits.abs().neg().exp().log1p();
+    loss.mean()
+}
+
+impl GanLoss {
+    /// Whether `generator_loss` needs the logits of real images.
+    pub fn needs_real_logits_for_generator(&self) -> bool {
+        matches!(self, GanLoss::RelativisticAverage)
+    }
+
+    /// Loss of the discriminator on a batch of real and a batch of generated images.
+    ///
+    /// The targets are the (possibly smoothed) labels of the real and fake images, only the
+    /// cross entropy and least squares variants use them.
+    pub fn discriminator_loss<B: Backend>(&self, real_logits: Tensor<B, 1>, fake_logits: Tensor<B, 1>, real_targets: Tensor<B, 1>, fake_targets: Tensor<B, 1>) -> Tensor<B, 1> {
+        match self {
+            GanLoss::NonSaturating => bce_with_logits(real_logits, real_targets) + bce_with_logits(fake_logits, fake_targets),
+            GanLoss::Hinge => (real_logits.neg() + 1.0).clamp_min(0.0).mean() + (fake_logits + 1.0).clamp_min(0.0).mean(),
+            GanLoss::LeastSquares => ((real_logits - real_targets).powf(2.0).mean() + (fake_logits - fake_targets).powf(2.0).mean()) * 0.5,
+            GanLoss::Wasserstein => fake_logits.mean() - real_logits.mean(),
+            GanLoss::RelativisticAverage => {
+                let real_relative = real_logits.clone() - fake_logits.clone().mean();
+                let fake_relative = fake_logits - real_logits.mean();
+                bce_with_logits(real_relative, real_targets) + bce_with_logits(fake_relative, fake_targets)
+            }
+        }
+    }
+
+    /// Loss of the generator on the logits of its images.
+    ///
+    /// `targets` are the labels the generator wants its images to get. `real_logits` is only read by
+    /// the relativistic variant, see `needs_real_logits_for_generator`.
+    pub fn generator_loss<B: Backend>(&self, fake_logits: Tensor<B, 1>, real_logits: Option<Tensor<B, 1>>, targets: Tensor<B, 1>) -> Tensor<B, 1> {
+        match self {
+            GanLoss::NonSaturating => bce_with_logits(fake_logits, targets),
+            GanLoss::Hinge | GanLoss::Wasserstein => fake_logits.mean().neg(),
+            GanLoss::LeastSquares => (fake_logits - targets).powf(2.0).mean() * 0.5,
+            GanLoss::RelativisticAverage => {
+                let real_logits = real_logits.expect("The relativistic loss needs the logits of real images");
+                let fake_relative = fake_logits.clone() - real_logits.clone().mean();
+                let real_relative = real_logits - fake_logits.mean();
+                // The roles swap: generated images should look more real than the real ones.
+                bce_with_logits(fake_relative, targets.clone()) + bce_with_logits(real_relative, targets.neg() + 1.0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::backend::NdArray;
+
+    use super::*;
+
+    type TestBackend = NdArray<f32>;
+
+    fn tensor(values: [f32; 2]) -> Tensor<TestBackend, 1> {
+        Tensor::from_floats(values)
+    }
+
+    /// Discriminator and generator loss on real logits [2, -1] and fake logits [-0.5, 2.5] with hard labels.
+    fn losses(loss: GanLoss) -> (f32, f32) {
+        let discriminator = loss.discriminator_loss(tensor([2.0, -1.0]), tensor([-0.5, 2.5]), tensor([1.0, 1.0]), tensor([0.0, 0.0]));
+        let real_logits = loss.needs_real_logits_for_generator().then(|| tensor([2.0, -1.0]));
+        let generator = loss.generator_loss(tensor([-0.5, 2.5]), real_logits, tensor([1.0, 1.0]));
+        (discriminator.into_scalar(), generator.into_scalar())
+    }
+
+    fn assert_losses(loss: GanLoss, (discriminator, generator): (f32, f32)) {
+        let (actual_discriminator, actual_generator) = losses(loss);
+        assert!((actual_discriminator - discriminator).abs() < 1e-4, "discriminator loss {actual_discriminator}, expected {discriminator}");
+        assert!((actual_generator - generator).abs() < 1e-4, "generator loss {actual_generator}, expected {generator}");
+    }
+
+    #[test]
+    fn bce_with_logits_matches_reference_and_stays_finite() {
+        let loss = bce_with_logits(Tensor::<TestBackend, 1>::from_floats([0.0, 3.0, -3.0]), Tensor::from_floats([0.5, 1.0, 0.0]));
+        assert!((loss.into_scalar() - 0.26344).abs() < 1e-4);
+        let saturated = bce_with_logits(Tensor::<TestBackend, 1>::from_floats([100.0, -100.0]), Tensor::from_floats([0.0, 1.0]));
+        assert!((saturated.into_scalar() - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn non_saturating_matches_reference() {
+        assert_losses(GanLoss::NonSaturating, (2.24658, 0.52648));
+    }
+
+    #[test]
+    fn non_saturating_uses_smoothed_targets() {
+        let loss = GanLoss::NonSaturating.discriminator_loss(tensor([2.0, -1.0]), tensor([-0.5, 2.5]), tensor([0.9, 0.9]), tensor([0.1, 0.1]));
+        assert!((loss.into_scalar() - 2.19658).abs() < 1e-4);
+    }
+
+    #[test]
+    fn hinge_matches_reference() {
+        assert_losses(GanLoss::Hinge, (3.0, -1.0));
+    }
+
+    #[test]
+    fn least_squares_matches_reference() {
+        assert_losses(GanLoss::LeastSquares, (2.875, 1.125));
+    }
+
+    #[test]
+    fn wasserstein_matches_reference() {
+        assert_losses(GanLoss::Wasserstein, (0.5, -1.0));
+    }
+
+    #[test]
+    fn relativistic_average_matches_reference() {
+        assert_losses(GanLoss::RelativisticAverage, (2.44019, 1.44019));
+    }
+}
@@ -1,4 +1,40 @@
-use burn::tensor::Data;
+use ::image::{ImageBuffer, Rgb};
+use burn::tensor::{backend::Backend, Data, Tensor};
 
-pub const IMAGE_WIDTH: usize = 64;
-pub const IMAGE_HEIGHT: usize = 64;
\ No newline at end of file
+/// Writes a `[height, width, 3]` tensor of pixels in [0, 1] as a PNG, see `Normalization::decode`.
+pub fn tensor_to_image<B: Backend>(path: &str, tensor: Tensor<B, 3>){
+    let [height, width, _] = tensor.dims();
+    let data: Data<f32, 3> = tensor.into_data().convert();
+
+    let image_data: Vec<u8> = data.value.iter().map(|pix_chan| (pix_chan.clamp(0.0, 1.0) * 255.0).round() as u8).collect();
+
+    let new_image: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_raw(width as u32, height as u32, image_data).unwrap();
+    new_image.save(path).unwrap();
+}
+
+/// Turns a single image `[3, height, width]` into the `[height, width, 3]` layout of `tensor_to_image`.
+pub fn to_channels_last<B: Backend>(image: Tensor<B, 3>) -> Tensor<B, 3> {
+    image.swap_dims(0, 1).swap_dims(1, 2)
+}
+
+/// Takes image `index` `[3, height, width]` out of a batch of generated images.
+pub fn nth_image<B: Backend>(images: Tensor<B, 4>, index: usize) -> Tensor<B, 3> {
+    let [_, channels, height, width] = images.dims();
+    images.slice([index..index + 1, 0..channels, 0..height, 0..width]).reshape([channels, height, width])
+}
+
+/// Tiles a batch of images `[rows * columns, 3, height, width]` into one contact sheet.
+pub fn image_grid<B: Backend>(images: Tensor<B, 4>, columns: usize) -> Tensor<B, 3> {
+    let [count, _, _, _] = images.dims();
+    assert!(count % columns == 0, "{count} images do not fill a grid with {columns} columns");
+
+    let rows = (0..count / columns)
+        .map(|row| {
+            let tiles = (0..columns)
+                .map(|column| to_channels_last(nth_image(images.clone(), row * columns + column)))
+                .collect();
+            Tensor::cat(tiles, 1)
+        })
+        .collect();
+    Tensor::cat(rows, 0)
+}
@@ -0,0 +1,73 @@
+use std::f64::consts::PI;
+
+use burn::config::Config;
+
+/// How the instance noise fades out over the run.
+#[derive(Config, Debug)]
+pub enum NoiseAnneal {
+    Constant,
+    /// Linear decrease to 0 over the first `steps` steps.
+    Linear { steps: usize },
+    /// Cosine decrease to 0 over the first `steps` steps.
+    Cosine { steps: usize },
+}
+
+/// Gaussian noise added to the real and generated images the discriminator sees.
+#[derive(Config, Debug)]
+pub struct InstanceNoiseConfig {
+    /// Standard deviation at the start of the run, 0 turns the noise off.
+    #[config(default = 0.3)]
+    pub std: f64,
+    #[config(default = "NoiseAnneal::Constant")]
+    pub anneal: NoiseAnneal,
+}
+
+impl InstanceNoiseConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.std < 0.0 {
+            return Err(format!("instance noise std is {}, expected a non-negative value", self.std));
+        }
+        if let NoiseAnneal::Linear { steps: 0 } | NoiseAnneal::Cosine { steps: 0 } = self.anneal {
+            return Err("instance noise annealing needs at least 1 step".to_string());
+        }
+        Ok(())
+    }
+
+    /// Standard deviation of the noise at step `step`.
+    pub fn std_at(&self, step: usize) -> f64 {
+        match self.anneal {
+            NoiseAnneal::Constant => self.std,
+            NoiseAnneal::Linear { steps } => self.std * (1.0 - step as f64 / steps as f64).max(0.0),
+            NoiseAnneal::Cosine { steps } => self.std * 0.5 * (1.0 + (PI * (step as f64 / steps as f64).min(1.0)).cos()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_stds(config: InstanceNoiseConfig, expected: &[(usize, f64)]) {
+        for &(step, std) in expected {
+            let actual = config.std_at(step);
+            assert!((actual - std).abs() < 1e-9, "{:?} at step {step} is {actual}, expected {std}", config.anneal);
+        }
+    }
+
+    #[test]
+    fn constant_keeps_initial_std() {
+        assert_stds(InstanceNoiseConfig::new().with_std(0.4), &[(0, 0.4), (1000, 0.4)]);
+    }
+
+    #[test]
+    fn linear_anneals_to_zero_and_stays() {
+        let config = InstanceNoiseConfig::new().with_std(0.4).with_anneal(NoiseAnneal::Linear { steps: 100 });
+        assert_stds(config, &[(0, 0.4), (25, 0.3), (100, 0.0), (150, 0.0)]);
+    }
+
+    #[test]
+    fn cosine_anneals_to_zero_and_stays() {
+        let config = InstanceNoiseConfig::new().with_std(0.4).with_anneal(NoiseAnneal::Cosine { steps: 100 });
+        assert_stds(config, &[(0, 0.4), (50, 0.2), (100, 0.0), (150, 0.0)]);
+    }
+}
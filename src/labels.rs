@@ -0,0 +1,131 @@
+use burn::{config::Config, tensor::{backend::Backend, Distribution, Tensor}};
+
+/// How far the discriminator targets are moved away from 1 for real and 0 for generated images.
+#[derive(Config, Debug)]
+pub enum LabelSmoothing {
+    /// Hard targets of 1 and 0.
+    None,
+    /// Real targets drawn from `Uniform(real_min, 1)`, generated targets stay 0.
+    OneSided { real_min: f64 },
+    /// Real targets drawn from `Uniform(real_min, 1)`, generated targets from `Uniform(0, fake_max)`.
+    TwoSided { real_min: f64, fake_max: f64 },
+}
+
+/// Targets of the discriminator and generator losses.
+#[derive(Config, Debug)]
+pub struct LabelConfig {
+    #[config(default = "LabelSmoothing::TwoSided { real_min: 0.8, fake_max: 0.2 }")]
+    pub smoothing: LabelSmoothing,
+    /// Probability that a discriminator target is swapped for the target of the other kind.
+    #[config(default = 0.0)]
+    pub flip_probability: f64,
+}
+
+impl LabelConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.smoothing {
+            LabelSmoothing::OneSided { real_min } | LabelSmoothing::TwoSided { real_min, .. } if !(0.0..=1.0).contains(&real_min) => {
+                return Err(format!("label smoothing real_min is {real_min}, expected a value in [0, 1]"));
+            }
+            LabelSmoothing::TwoSided { fake_max, .. } if !(0.0..=1.0).contains(&fake_max) => {
+                return Err(format!("label smoothing fake_max is {fake_max}, expected a value in [0, 1]"));
+            }
+            _ => {}
+        }
+        if !(0.0..=1.0).contains(&self.flip_probability) {
+            return Err(format!("flip_probability is {}, expected a value in [0, 1]", self.flip_probability));
+        }
+        Ok(())
+    }
+
+    fn real_targets<B: Backend>(&self, batch_size: usize) -> Tensor<B, 1> {
+        match self.smoothing {
+            LabelSmoothing::None => Tensor::ones([batch_size]),
+            LabelSmoothing::OneSided { real_min } | LabelSmoothing::TwoSided { real_min, .. } => Tensor::random([batch_size], Distribution::Uniform(real_min, 1.0)),
+        }
+    }
+
+    fn fake_targets<B: Backend>(&self, batch_size: usize) -> Tensor<B, 1> {
+        match self.smoothing {
+            LabelSmoothing::None | LabelSmoothing::OneSided { .. } => Tensor::zeros([batch_size]),
+            LabelSmoothing::TwoSided { fake_max, .. } => Tensor::random([batch_size], Distribution::Uniform(0.0, fake_max)),
+        }
+    }
+
+    /// Smoothed and randomly flipped targets of the real and the generated images.
+    pub fn discriminator_targets<B: Backend>(&self, batch_size: usize) -> (Tensor<B, 1>, Tensor<B, 1>) {
+        let real = self.real_targets(batch_size);
+        let fake = self.fake_targets(batch_size);
+        if self.flip_probability == 0.0 {
+            return (real, fake);
+        }
+        (flip(real, self.flip_probability), flip(fake, self.flip_probability))
+    }
+
+    /// Targets the generator wants its images to get, the smoothed real targets without flips.
+    pub fn generator_targets<B: Backend>(&self, batch_size: usize) -> Tensor<B, 1> {
+        self.real_targets(batch_size)
+    }
+}
+
+/// Replaces each target `t` by `1 - t` with probability `probability`.
+fn flip<B: Backend>(targets: Tensor<B, 1>, probability: f64) -> Tensor<B, 1> {
+    let flipped = targets.random_like(Distribution::Bernoulli(probability));
+    targets.clone() + flipped * (targets.mul_scalar(-2.0) + 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::backend::NdArray;
+
+    use super::*;
+
+    type TestBackend = NdArray<f32>;
+
+    const BATCH_SIZE: usize = 256;
+
+    fn values(targets: Tensor<TestBackend, 1>) -> Vec<f32> {
+        targets.into_data().value
+    }
+
+    fn assert_within(targets: Tensor<TestBackend, 1>, min: f32, max: f32) {
+        for target in values(targets) {
+            assert!((min..=max).contains(&target), "target {target} outside [{min}, {max}]");
+        }
+    }
+
+    #[test]
+    fn no_smoothing_gives_hard_targets() {
+        let config = LabelConfig::new().with_smoothing(LabelSmoothing::None);
+        let (real, fake) = config.discriminator_targets::<TestBackend>(BATCH_SIZE);
+        assert_within(real, 1.0, 1.0);
+        assert_within(fake, 0.0, 0.0);
+        assert_within(config.generator_targets::<TestBackend>(BATCH_SIZE), 1.0, 1.0);
+    }
+
+    #[test]
+    fn one_sided_smoothing_only_moves_real_targets() {
+        let config = LabelConfig::new().with_smoothing(LabelSmoothing::OneSided { real_min: 0.7 });
+        let (real, fake) = config.discriminator_targets::<TestBackend>(BATCH_SIZE);
+        assert_within(real, 0.7, 1.0);
+        assert_within(fake, 0.0, 0.0);
+    }
+
+    #[test]
+    fn two_sided_smoothing_moves_both_targets() {
+        let config = LabelConfig::new();
+        let (real, fake) = config.discriminator_targets::<TestBackend>(BATCH_SIZE);
+        assert_within(real, 0.8, 1.0);
+        assert_within(fake, 0.0, 0.2);
+        assert_within(config.generator_targets::<TestBackend>(BATCH_SIZE), 0.8, 1.0);
+    }
+
+    #[test]
+    fn certain_flips_swap_the_targets() {
+        let config = LabelConfig::new().with_smoothing(LabelSmoothing::None).with_flip_probability(1.0);
+        let (real, fake) = config.discriminator_targets::<TestBackend>(BATCH_SIZE);
+        assert_within(real, 0.0, 0.0);
+        assert_within(fake, 1.0, 1.0);
+        assert_within(config.generator_targets::<TestBackend>(BATCH_SIZE), 1.0, 1.0);
+    }
+}
@@ -0,0 +1,110 @@
+use std::f64::consts::PI;
+
+use burn::config::Config;
+
+/// How the learning rate of a network changes over the run.
+///
+/// The rate is a pure function of the global step, so a resumed run continues the schedule
+/// from the step in its checkpoint.
+#[derive(Config, Debug)]
+pub enum LrSchedule {
+    Constant,
+    /// Linear increase from 0 to the base rate over the first `steps` steps, constant afterwards.
+    Warmup { steps: usize },
+    /// The base rate multiplied by `gamma` every `epochs` epochs.
+    StepDecay { epochs: usize, gamma: f64 },
+    /// Cosine annealing from the base rate to `min_learning_rate` over the whole run.
+    Cosine { min_learning_rate: f64 },
+    /// The base rate, decayed linearly to 0 over the last `epochs` epochs.
+    LinearDecay { epochs: usize },
+}
+
+impl LrSchedule {
+    pub fn validate(&self, learning_rate: f64, num_epochs: usize) -> Result<(), String> {
+        match *self {
+            LrSchedule::Constant => Ok(()),
+            LrSchedule::Warmup { steps: 0 } => Err("warmup needs at least 1 step".to_string()),
+            LrSchedule::StepDecay { epochs: 0, .. } => Err("step decay needs an interval of at least 1 epoch".to_string()),
+            LrSchedule::StepDecay { gamma, .. } if !(gamma.is_finite() && gamma > 0.0 && gamma <= 1.0) => Err(format!("step decay gamma is {gamma}, expected a value in (0, 1]")),
+            LrSchedule::Cosine { min_learning_rate } if !(min_learning_rate.is_finite() && (0.0..=learning_rate).contains(&min_learning_rate)) => {
+                Err(format!("cosine min_learning_rate is {min_learning_rate}, expected a value in [0, {learning_rate}]"))
+            }
+            LrSchedule::LinearDecay { epochs } if epochs == 0 || epochs > num_epochs => {
+                Err(format!("linear decay over {epochs} epochs, expected between 1 and num_epochs ({num_epochs})"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Learning rate of step `step` (counted from 0 over the whole run) for the base rate `learning_rate`.
+    pub fn learning_rate(&self, learning_rate: f64, step: usize, steps_per_epoch: usize, num_epochs: usize) -> f64 {
+        let total_steps = (steps_per_epoch * num_epochs).max(1);
+        match *self {
+            LrSchedule::Constant => learning_rate,
+            LrSchedule::Warmup { steps } => learning_rate * ((step + 1) as f64 / steps as f64).min(1.0),
+            LrSchedule::StepDecay { epochs, gamma } => learning_rate * gamma.powi((step / (epochs * steps_per_epoch).max(1)) as i32),
+            LrSchedule::Cosine { min_learning_rate } => {
+                let progress = (step as f64 / total_steps as f64).min(1.0);
+                min_learning_rate + (learning_rate - min_learning_rate) * 0.5 * (1.0 + (PI * progress).cos())
+            }
+            LrSchedule::LinearDecay { epochs } => {
+                let decay_steps = (epochs * steps_per_epoch).max(1);
+                let remaining = total_steps.saturating_sub(step);
+                learning_rate * (remaining as f64 / decay_steps as f64).min(1.0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS_PER_EPOCH: usize = 10;
+
+    const NUM_EPOCHS: usize = 4;
+
+    fn assert_rates(schedule: LrSchedule, expected: &[(usize, f64)]) {
+        for &(step, rate) in expected {
+            let actual = schedule.learning_rate(1.0, step, STEPS_PER_EPOCH, NUM_EPOCHS);
+            assert!((actual - rate).abs() < 1e-9, "{schedule:?} at step {step} is {actual}, expected {rate}");
+        }
+    }
+
+    #[test]
+    fn constant_keeps_base_rate() {
+        assert_rates(LrSchedule::Constant, &[(0, 1.0), (39, 1.0)]);
+    }
+
+    #[test]
+    fn warmup_ramps_up_then_stays() {
+        assert_rates(LrSchedule::Warmup { steps: 4 }, &[(0, 0.25), (2, 0.75), (3, 1.0), (30, 1.0)]);
+    }
+
+    #[test]
+    fn step_decay_multiplies_every_interval() {
+        assert_rates(LrSchedule::StepDecay { epochs: 1, gamma: 0.5 }, &[(0, 1.0), (9, 1.0), (10, 0.5), (25, 0.25)]);
+    }
+
+    #[test]
+    fn cosine_anneals_to_min_rate() {
+        assert_rates(LrSchedule::Cosine { min_learning_rate: 0.1 }, &[(0, 1.0), (20, 0.55), (40, 0.1)]);
+    }
+
+    #[test]
+    fn linear_decay_only_covers_last_epochs() {
+        assert_rates(LrSchedule::LinearDecay { epochs: 2 }, &[(0, 1.0), (20, 1.0), (30, 0.5), (39, 0.05)]);
+    }
+
+    #[test]
+    fn validate_rejects_non_finite_rates() {
+        for gamma in [f64::NAN, f64::INFINITY] {
+            assert!(LrSchedule::StepDecay { epochs: 1, gamma }.validate(1.0, NUM_EPOCHS).is_err(), "gamma {gamma} should be rejected");
+        }
+        for min_learning_rate in [f64::NAN, f64::NEG_INFINITY] {
+            assert!(LrSchedule::Cosine { min_learning_rate }.validate(1.0, NUM_EPOCHS).is_err(), "min_learning_rate {min_learning_rate} should be rejected");
+        }
+        assert!(LrSchedule::StepDecay { epochs: 1, gamma: 1.0 }.validate(1.0, NUM_EPOCHS).is_ok());
+        assert!(LrSchedule::Cosine { min_learning_rate: 1.0 }.validate(1.0, NUM_EPOCHS).is_ok());
+    }
+}
@@ -1,47 +1,137 @@
-use std::env::args;
-use burn::backend::ndarray::NdArrayDevice;
-use burn::backend::{LibTorch, NdArray};
-use burn::backend::libtorch::LibTorchDevice;
-use burn::backend::wgpu::{GraphicsApi, self, OpenGl};
-use burn::optim::AdamConfig;
-use burn::backend::{Autodiff, Wgpu, wgpu::AutoGraphicsApi, Fusion};
-use burn::tensor::f16;
-use models::{GeneratorConfig, DiscriminatorConfig};
+use std::path::Path;
+use std::process::exit;
 
+use burn::config::Config;
+use burn::data::dataset::Dataset;
+use burn::tensor::backend::AutodiffBackend;
+use clap::Parser;
+
+use backend::with_backend;
+use cli::{Cli, Command, InspectArgs, ResumeArgs, SampleArgs, TrainArgs};
+use sampling::SampleOutput;
+
+mod backend;
+mod bake;
+mod checkpoint;
+mod ema;
+mod cli;
 mod image;
 mod data_loader;
 mod models;
 mod training;
-mod leaky_relu;
-mod cross_entropy_loss;
+mod sampling;
+mod activation;
+mod gan_loss;
+mod regularization;
+mod spectral_norm;
+mod optimizer;
+mod lr_schedule;
+mod balance;
+mod labels;
+mod instance_noise;
+mod augment;
+mod normalization;
+mod snapshot;
 
 fn main() {
-    let args: Vec<String> = args().collect();
-    if args.len() > 1 && args[1] == "--bake" {
-        data_loader::bake_image_dataset("../../ML_data/img_align_celeba/");
-        println!("Baking of Images into Sqlite finished.");
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Bake(args) => {
+            if let Err(err) = bake::bake_image_dataset(&args.bake_config(), args.resume, args.overwrite) {
+                eprintln!("Bake failed: {err}");
+                exit(1);
+            }
+            println!("Baking of Images into Sqlite finished.");
+        }
+        Command::Train(args) => run(*args),
+        Command::Inspect(args) => inspect(args),
+        Command::Sample(args) => sample(args),
+        Command::Resume(args) => resume(args),
     }
-    else {
-        run();
+}
+
+fn run(args: TrainArgs) {
+    let dataset_normalization = args.dataset_normalization.then(|| {
+        let dataset = data_loader::make_image_dataset(&args.sqlite, data_loader::TRAIN_SPLIT)
+            .unwrap_or_else(|| panic!("Dataset {} should contain a \"{}\" split", args.sqlite, data_loader::TRAIN_SPLIT));
+        let normalization = normalization::Normalization::from_dataset(&dataset);
+        println!("Dataset normalization: mean {:?}, std {:?}", normalization.mean, normalization.std);
+        normalization
+    });
+    let config = args.training_config(dataset_normalization);
+    if let Err(err) = config.validate() {
+        eprintln!("Invalid training config: {err}");
+        exit(1);
     }
+    println!("Training on backend {:?}", args.backend);
+
+    with_backend!(args.backend, |MyAutodiffBackend, device| {
+        training::train::<MyAutodiffBackend>(
+            &args.artifact_dir,
+            &args.sqlite,
+            config,
+            device,
+        )
+    });
 }
 
-fn run() {
-    // // type MyBackend = Wgpu<burn::backend::wgpu::AutoGraphicsApi, f32, i32>;
-    type MyBackend = Wgpu<OpenGl, f32, i32>;//Fusion<MyBackend>;
-    type MyAutodiffBackend = Autodiff<MyBackend>;
+fn resume(args: ResumeArgs) {
+    println!("Resuming on backend {:?}", args.backend);
 
-    let device = burn::backend::wgpu::WgpuDevice::BestAvailable;
-    // type MyAutodiffBackend = Autodiff<LibTorch<f32>>;
-    // let device = LibTorchDevice::Cpu;
+    with_backend!(args.backend, |MyAutodiffBackend, device| {
+        training::resume::<MyAutodiffBackend>(
+            &args.artifact_dir,
+            &args.sqlite,
+            args.checkpoint,
+            device,
+        )
+    });
+}
 
-    // type MyAutodiffBackend = Autodiff<NdArray<f32>>;
-    // let device = NdArrayDevice::Cpu;
+fn sample(args: SampleArgs) {
+    let output = match args.grid {
+        Some((rows, columns)) => SampleOutput::Grid { rows, columns },
+        None => SampleOutput::Individual { count: args.count },
+    };
+    let output_dir = args.output_dir.unwrap_or_else(|| format!("{}/samples", args.artifact_dir));
 
-    training::train::<MyAutodiffBackend>(
-        "./artifacts",
-        training::TrainingConfig::new(GeneratorConfig::new(), DiscriminatorConfig::new()),
-        device,
-    );
+    with_backend!(args.backend, |MyAutodiffBackend, device| {
+        sampling::sample::<<MyAutodiffBackend as AutodiffBackend>::InnerBackend>(
+            &args.artifact_dir,
+            args.checkpoint,
+            !args.no_ema,
+            output,
+            args.seed,
+            &output_dir,
+            device,
+        )
+    });
+}
+
+fn inspect(args: InspectArgs) {
+    if Path::new(&args.sqlite).exists() {
+        for split in [data_loader::TRAIN_SPLIT, data_loader::VALID_SPLIT] {
+            match data_loader::make_image_dataset(&args.sqlite, split) {
+                Some(dataset) => {
+                    println!("Dataset {}: {} images in split \"{split}\"", args.sqlite, dataset.len());
+                    if let Some(item) = dataset.get(0) {
+                        println!("Image shape: {:?}", item.shape);
+                    }
+                }
+                None => println!("Dataset {} has no split \"{split}\".", args.sqlite),
+            }
+        }
+        match bake::BakeConfig::load(bake::metadata_path(&args.sqlite)) {
+            Ok(metadata) => println!("Baked with:\n{metadata}"),
+            Err(_) => println!("No bake metadata found for {}.", args.sqlite),
+        }
+    } else {
+        println!("Dataset {} does not exist.", args.sqlite);
+    }
 
-}
\ No newline at end of file
+    let config_path = format!("{}/config.json", args.artifact_dir);
+    match training::TrainingConfig::load(&config_path) {
+        Ok(config) => println!("Training config {config_path}:\n{config}"),
+        Err(_) => println!("No training config found at {config_path}."),
+    }
+}
@@ -1,108 +1,179 @@
-use burn::{module::Module, config::Config, nn::{conv::{ConvTranspose2d, Conv2d, ConvTranspose2dConfig, Conv2dConfig}, BatchNorm, ReLU, BatchNormConfig, loss::{CrossEntropyLoss, BinaryCrossEntropyLossConfig}, Linear, LinearConfig, Initializer, Dropout, DropoutConfig}, tensor::{Tensor, backend::Backend, activation::sigmoid, Int}, train::ClassificationOutput, backend::autodiff::ops::Init};
-use crate::leaky_relu::leaky_relu;
+use burn::{module::Module, config::Config, nn::{conv::{ConvTranspose2dConfig, Conv2dConfig}, BatchNorm, BatchNormConfig, LinearConfig, Initializer, Dropout, DropoutConfig}, tensor::{Tensor, backend::Backend}};
+use crate::{activation::Activation, spectral_norm::{Conv2dLayer, ConvTranspose2dLayer, LinearLayer}};
+
+/// Image sizes the networks can be built for.
+pub const SUPPORTED_IMAGE_SIZES: [usize; 4] = [32, 64, 128, 256];
+/// Spatial size the generator projects the latents to and the discriminator reduces the image to.
+const BASE_RESOLUTION: usize = 4;
+/// Widest feature map relative to `feature_map_size`, so large images do not blow up the channel count.
+const MAX_FEATURE_MULTIPLIER: usize = 16;
+/// Number of generator blocks that apply dropout, counted from the lowest resolution.
+const DROPOUT_BLOCKS: usize = 2;
+
+/// Normalization of the hidden layers of a network.
+#[derive(Config, Debug)]
+pub enum Norm {
+    /// Batch norm after every hidden convolution.
+    Batch,
+    /// Spectral normalization of the weights of every layer, see `spectral_norm`.
+    Spectral,
+    None,
+}
+
+impl Norm {
+    fn batch_norm<B: Backend>(&self, channels: usize) -> Option<BatchNorm<B, 2>> {
+        matches!(self, Norm::Batch).then(|| BatchNormConfig::new(channels).init())
+    }
+
+    fn spectral(&self) -> bool {
+        matches!(self, Norm::Spectral)
+    }
+}
+
+fn validate_image_size(image_size: usize) -> Result<(), String> {
+    if !SUPPORTED_IMAGE_SIZES.contains(&image_size) {
+        return Err(format!("image_size is {image_size}, expected one of {SUPPORTED_IMAGE_SIZES:?}"));
+    }
+    Ok(())
+}
+
+/// Number of ×2 up- or down-sampling steps between `BASE_RESOLUTION` and a supported `image_size`.
+fn resolution_steps(image_size: usize) -> usize {
+    (image_size / BASE_RESOLUTION).trailing_zeros() as usize
+}
+
+/// Channels of a feature map that is `steps` ×2 steps away from full resolution.
+fn feature_channels(feature_map_size: usize, steps: usize) -> usize {
+    feature_map_size * (1 << steps).min(MAX_FEATURE_MULTIPLIER)
+}
 
 // See: https://pytorch.org/tutorials/beginner/dcgan_faces_tutorial.html
 #[derive(Module, Debug)]
 pub struct Generator<B: Backend>{
-    projection: Linear<B>,
-    conv1: ConvTranspose2d<B>,
-    batch_norm1: BatchNorm<B,2>,
-    dropout1: Dropout,
-    conv2: ConvTranspose2d<B>,
-    batch_norm2: BatchNorm<B,2>,
-    dropout2: Dropout,
-    conv3: ConvTranspose2d<B>,
-    batch_norm3: BatchNorm<B,2>,
-    conv4: ConvTranspose2d<B>,
+    latent_vector_size: usize,
+    projection: LinearLayer<B>,
+    projection_channels: usize,
+    blocks: Vec<UpsampleBlock<B>>,
+    output_conv: ConvTranspose2dLayer<B>,
+}
+
+#[derive(Module, Debug)]
+pub struct UpsampleBlock<B: Backend>{
+    conv: ConvTranspose2dLayer<B>,
+    batch_norm: Option<BatchNorm<B,2>>,
+    /// Dropout probability, 0 for blocks past `DROPOUT_BLOCKS`.
+    dropout: Dropout,
+    activation: Activation,
 }
 
 #[derive(Config, Debug)]
 pub struct GeneratorConfig {
     #[config(default = "100")]
     pub latent_vector_size: usize,
+    /// Base width of the feature maps: the last one before the output has twice this many channels
+    /// and every lower resolution doubles it, up to `MAX_FEATURE_MULTIPLIER` times this.
     #[config(default = "64")]
-    feature_map_size: usize,
+    pub feature_map_size: usize,
     #[config(default = "0.5")]
-    dropout: f64,
+    pub dropout: f64,
+    /// Width and height of the generated images, one of `SUPPORTED_IMAGE_SIZES`.
+    #[config(default = "64")]
+    pub image_size: usize,
+    #[config(default = "Norm::Batch")]
+    pub norm: Norm,
+    #[config(default = "Activation::leaky_relu()")]
+    pub activation: Activation,
 }
 
 impl GeneratorConfig{
+    /// Checks that the settings describe a generator that can be built.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.latent_vector_size == 0 {
+            return Err("latent_vector_size has to be at least 1".to_string());
+        }
+        if self.feature_map_size == 0 {
+            return Err("feature_map_size has to be at least 1".to_string());
+        }
+        if !(0.0..1.0).contains(&self.dropout) {
+            return Err(format!("dropout is {}, expected a probability in [0, 1)", self.dropout));
+        }
+        validate_image_size(self.image_size)
+    }
+
     pub fn init<B: Backend>(&self, conv_initializer: &Initializer) -> Generator<B> {
-        Generator { 
-            projection: LinearConfig::new(100, 1024 * 4 * 4).init(),
-            conv1: ConvTranspose2dConfig::new([self.feature_map_size * 16, self.feature_map_size * 8], [4,4]).with_stride([2, 2]).with_padding([1,1]).with_initializer(conv_initializer.clone()).init(),
-            batch_norm1: BatchNormConfig::new(self.feature_map_size * 8).init(),
-            dropout1: DropoutConfig::new(self.dropout).init(),
-            conv2: ConvTranspose2dConfig::new([self.feature_map_size * 8, self.feature_map_size * 4], [4,4]).with_stride([2,2]).with_padding([1,1]).with_initializer(conv_initializer.clone()).init(),
-            batch_norm2: BatchNormConfig::new(self.feature_map_size * 4).init(),
-            dropout2: DropoutConfig::new(self.dropout).init(),
-            conv3: ConvTranspose2dConfig::new([self.feature_map_size * 4, self.feature_map_size * 2], [4,4]).with_stride([2,2]).with_padding([1,1]).with_initializer(conv_initializer.clone()).init(),
-            batch_norm3: BatchNormConfig::new(self.feature_map_size * 2).init(),
-            conv4: ConvTranspose2dConfig::new([self.feature_map_size * 2, 3], [4,4]).with_stride([2,2]).with_padding([1,1]).with_initializer(conv_initializer.clone()).init(),
+        if let Err(err) = self.validate() {
+            panic!("Invalid generator config: {err}");
         }
+        let steps = resolution_steps(self.image_size);
+        let projection_channels = feature_channels(self.feature_map_size, steps);
+
+        // Every block doubles the resolution, the output conv does the last doubling.
+        let blocks = (0..steps - 1)
+            .map(|block| UpsampleBlock {
+                conv: ConvTranspose2dLayer::new(&ConvTranspose2dConfig::new([feature_channels(self.feature_map_size, steps - block), feature_channels(self.feature_map_size, steps - block - 1)], [4,4]).with_stride([2,2]).with_padding([1,1]).with_initializer(conv_initializer.clone()), self.norm.spectral()),
+                batch_norm: self.norm.batch_norm(feature_channels(self.feature_map_size, steps - block - 1)),
+                dropout: DropoutConfig::new(if block < DROPOUT_BLOCKS { self.dropout } else { 0.0 }).init(),
+                activation: self.activation.clone(),
+            })
+            .collect();
+
+        Generator {
+            latent_vector_size: self.latent_vector_size,
+            projection: LinearLayer::new(&LinearConfig::new(self.latent_vector_size, projection_channels * BASE_RESOLUTION * BASE_RESOLUTION), self.norm.spectral()),
+            projection_channels,
+            blocks,
+            output_conv: ConvTranspose2dLayer::new(&ConvTranspose2dConfig::new([feature_channels(self.feature_map_size, 1), 3], [4,4]).with_stride([2,2]).with_padding([1,1]).with_initializer(conv_initializer.clone()), self.norm.spectral()),
+        }
+    }
+}
+
+impl<B: Backend> UpsampleBlock<B> {
+    fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let x = self.conv.forward(x);
+        let x = match &self.batch_norm {
+            Some(batch_norm) => batch_norm.forward(x),
+            None => x,
+        };
+        let x = self.activation.forward(x);
+        self.dropout.forward(x)
     }
 }
 
 impl<B: Backend> Generator<B> {
-    pub fn forward(&self, latents: Tensor<B, 2>) -> Tensor<B, 4> {
+    /// Returns the batch size of `latents` after checking they fit the projection.
+    fn check_latents(&self, latents: &Tensor<B, 2>) -> usize {
         let [batch_size, latents_size] = latents.dims();
+        assert_eq!(latents_size, self.latent_vector_size, "Generator expects latent vectors of size {}", self.latent_vector_size);
+        batch_size
+    }
 
-        // Create a channel at the second dimension.
-        let x = latents;
-
-        let x = self.projection.forward(x);
-        let x = x.reshape([batch_size, 1024, 4, 4]);
-
-        // Round 1
-        let x = self.conv1.forward(x);
-        let x = self.batch_norm1.forward(x);
-        let x = leaky_relu(x);
-        let x = self.dropout1.forward(x);
-
-        // Round 2
-        let x = self.conv2.forward(x);
-        let x = self.batch_norm2.forward(x);
-        let x = leaky_relu(x);
-        let x = self.dropout2.forward(x);
-
-        // Round 3
-        let x = self.conv3.forward(x);
-        let x = self.batch_norm3.forward(x);
-        let x = leaky_relu(x);
-        
-        // Round 4
-        let x = self.conv4.forward(x);
+    pub fn forward(&self, latents: Tensor<B, 2>) -> Tensor<B, 4> {
+        let batch_size = self.check_latents(&latents);
+
+        let x = self.projection.forward(latents);
+        let x = x.reshape([batch_size, self.projection_channels, BASE_RESOLUTION, BASE_RESOLUTION]);
+
+        let x = self.blocks.iter().fold(x, |x, block| block.forward(x));
+
+        let x = self.output_conv.forward(x);
         x.tanh() // [batch, 3, height, width]
     }
     pub fn forward_print_sizes(&self, latents: Tensor<B, 2>) -> Tensor<B, 4> {
-        let [batch_size, latents_size] = latents.dims();
+        let batch_size = self.check_latents(&latents);
         println!("latents: {:?}", latents.shape());
 
-        // Create a channel at the second dimension.
-        let x = latents;
-
-        let x = self.projection.forward(x);
-        let x = x.reshape([batch_size, 1024, 4, 4]);
+        let x = self.projection.forward(latents);
+        let x = x.reshape([batch_size, self.projection_channels, BASE_RESOLUTION, BASE_RESOLUTION]);
         println!("projection: {:?}", x.shape());
 
-        // Round 1
-        let x = self.conv1.forward(x);
-        let x = self.batch_norm1.forward(x);
-        let x = leaky_relu(x);
-        println!("conv1: {:?}", x.shape());
-        // Round 2
-        let x = self.conv2.forward(x);
-        let x = self.batch_norm2.forward(x);
-        let x = leaky_relu(x);    
-        println!("conv2: {:?}", x.shape());
-        // Round 3
-        let x = self.conv3.forward(x);
-        let x = self.batch_norm3.forward(x);
-        let x = leaky_relu(x);
-        println!("conv3: {:?}", x.shape());
-        // Round 4
-        let x = self.conv4.forward(x);
-        println!("conv4: {:?}", x.shape());
+        let x = self.blocks.iter().enumerate().fold(x, |x, (index, block)| {
+            let x = block.forward(x);
+            println!("block{}: {:?}", index + 1, x.shape());
+            x
+        });
+
+        let x = self.output_conv.forward(x);
+        println!("output_conv: {:?}", x.shape());
         x.tanh() // [batch, 3, height, width]
     }
 }
@@ -110,64 +181,122 @@ impl<B: Backend> Generator<B> {
 
 #[derive(Module, Debug)]
 pub struct Discriminator<B: Backend>{
-    conv1: Conv2d<B>,
-    conv2: Conv2d<B>,
-    norm2: BatchNorm<B,2>,
-    conv3: Conv2d<B>,
-    norm3: BatchNorm<B,2>,
-    conv4: Conv2d<B>,
-    norm4: BatchNorm<B,2>,
-    final_conv: Conv2d<B>,
+    blocks: Vec<DownsampleBlock<B>>,
+    final_conv: Conv2dLayer<B>,
+}
+
+#[derive(Module, Debug)]
+pub struct DownsampleBlock<B: Backend>{
+    conv: Conv2dLayer<B>,
+    norm: Option<BatchNorm<B,2>>,
+    activation: Activation,
 }
 
 #[derive(Config, Debug)]
 pub struct DiscriminatorConfig{
+    /// Channels of the first feature map, the lower resolutions use multiples of it.
     #[config(default = "64")]
-    feature_map_size: usize,
+    pub feature_map_size: usize,
+    /// Width and height of the judged images, one of `SUPPORTED_IMAGE_SIZES`.
+    #[config(default = "64")]
+    pub image_size: usize,
+    /// Gradient penalties judge every image on its own and need a norm other than `Batch`.
+    #[config(default = "Norm::Batch")]
+    pub norm: Norm,
+    #[config(default = "Activation::leaky_relu()")]
+    pub activation: Activation,
 }
 
 impl DiscriminatorConfig{
+    /// Checks that the settings describe a discriminator that can be built.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.feature_map_size == 0 {
+            return Err("feature_map_size has to be at least 1".to_string());
+        }
+        validate_image_size(self.image_size)
+    }
+
     pub fn init<B: Backend>(&self, conv_initializer: &Initializer) -> Discriminator<B> {
-        Discriminator { 
-            conv1: Conv2dConfig::new([3, self.feature_map_size], [4,4]).with_stride([2,2]).with_padding(burn::nn::PaddingConfig2d::Explicit(1, 1)).with_initializer(conv_initializer.clone()).init(),
-            conv2: Conv2dConfig::new([self.feature_map_size, self.feature_map_size * 2], [4,4]).with_stride([2,2]).with_padding(burn::nn::PaddingConfig2d::Explicit(1, 1)).with_initializer(conv_initializer.clone()).init(),
-            norm2: BatchNormConfig::new(self.feature_map_size * 2).init(),
-            conv3: Conv2dConfig::new([self.feature_map_size * 2, self.feature_map_size * 4], [4,4]).with_stride([2,2]).with_padding(burn::nn::PaddingConfig2d::Explicit(1, 1)).with_initializer(conv_initializer.clone()).init(),
-            norm3: BatchNormConfig::new(self.feature_map_size * 4).init(),
-            conv4: Conv2dConfig::new([self.feature_map_size * 4, self.feature_map_size * 8], [4,4]).with_stride([2,2]).with_padding(burn::nn::PaddingConfig2d::Explicit(1, 1)).with_initializer(conv_initializer.clone()).init(),
-            norm4: BatchNormConfig::new(self.feature_map_size * 8).init(),
-            final_conv: Conv2dConfig::new([self.feature_map_size * 8, 1], [4,4]).with_initializer(conv_initializer.clone()).init() 
+        if let Err(err) = self.validate() {
+            panic!("Invalid discriminator config: {err}");
+        }
+        let steps = resolution_steps(self.image_size);
+
+        // Every block halves the resolution, the first one works on the raw image and skips the norm.
+        let blocks = (0..steps)
+            .map(|block| {
+                let in_channels = if block == 0 { 3 } else { feature_channels(self.feature_map_size, block - 1) };
+                let out_channels = feature_channels(self.feature_map_size, block);
+                DownsampleBlock {
+                    conv: Conv2dLayer::new(&Conv2dConfig::new([in_channels, out_channels], [4,4]).with_stride([2,2]).with_padding(burn::nn::PaddingConfig2d::Explicit(1, 1)).with_initializer(conv_initializer.clone()), self.norm.spectral()),
+                    norm: if block > 0 { self.norm.batch_norm(out_channels) } else { None },
+                    activation: self.activation.clone(),
+                }
+            })
+            .collect();
+
+        Discriminator {
+            blocks,
+            final_conv: Conv2dLayer::new(&Conv2dConfig::new([feature_channels(self.feature_map_size, steps - 1), 1], [BASE_RESOLUTION, BASE_RESOLUTION]).with_initializer(conv_initializer.clone()), self.norm.spectral()),
         }
     }
 }
 
+impl<B: Backend> DownsampleBlock<B> {
+    fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let x = self.conv.forward(x);
+        let x = match &self.norm {
+            Some(norm) => norm.forward(x),
+            None => x,
+        };
+        self.activation.forward(x)
+    }
+}
+
 impl<B: Backend> Discriminator<B> {
+    /// Returns one raw logit per image, positive values mean the image is judged real.
     pub fn forward(&self, images: Tensor<B, 4>) -> Tensor<B, 1> {
-        let [batch_size, channels, width, height] = images.dims();
+        let [batch_size, _channels, _width, _height] = images.dims();
 
-        // Round 1
-        let x = self.conv1.forward(images);
-        let x = leaky_relu(x);
+        let x = self.blocks.iter().fold(images, |x, block| block.forward(x));
 
+        // Final Conv, the losses apply the sigmoid themselves if they need one
+        let x = self.final_conv.forward(x);
+        x.reshape([batch_size])
+    }
+}
 
-        // Round 2
-        let x = self.conv2.forward(x);
-        let x = self.norm2.forward(x);
-        let x = leaky_relu(x);
+#[cfg(test)]
+mod tests {
+    use burn::backend::NdArray;
 
-        // Round 3
-        let x = self.conv3.forward(x); 
-        let x = self.norm3.forward(x);
-        let x = leaky_relu(x);
+    use super::*;
 
-        // Round 4
-        let x = self.conv4.forward(x);
-        let x = self.norm4.forward(x);
-        let x = leaky_relu(x);
-        
-        // Final Conv and Tanh
-        let x = self.final_conv.forward(x);
-        let x = x.reshape([batch_size]);
-        sigmoid(x)
+    type TestBackend = NdArray<f32>;
+
+    const INITIALIZER: Initializer = Initializer::Normal { mean: 0.0, std: 0.02 };
+
+    #[test]
+    fn generator_outputs_configured_image_size() {
+        for image_size in SUPPORTED_IMAGE_SIZES {
+            let config = GeneratorConfig::new().with_latent_vector_size(8).with_feature_map_size(2).with_image_size(image_size);
+            let generator = config.init::<TestBackend>(&INITIALIZER);
+            let latents = Tensor::<TestBackend, 2>::random([2, 8], burn::tensor::Distribution::Normal(0.0, 1.0));
+
+            let x = generator.projection.forward(latents.clone()).reshape([2, generator.projection_channels, BASE_RESOLUTION, BASE_RESOLUTION]);
+            let last_feature_map = generator.blocks.iter().fold(x, |x, block| block.forward(x));
+            assert_eq!(last_feature_map.dims(), [2, 4, image_size / 2, image_size / 2], "last feature map for image size {image_size}");
+            assert_eq!(generator.forward(latents).dims(), [2, 3, image_size, image_size]);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn discriminator_judges_configured_image_size() {
+        for image_size in SUPPORTED_IMAGE_SIZES {
+            let config = DiscriminatorConfig::new().with_feature_map_size(2).with_image_size(image_size);
+            let discriminator = config.init::<TestBackend>(&INITIALIZER);
+            let images = Tensor::<TestBackend, 4>::zeros([2, 3, image_size, image_size]);
+            assert_eq!(discriminator.forward(images).dims(), [2]);
+        }
+    }
+}
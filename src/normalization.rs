@@ -0,0 +1,117 @@
+use burn::{config::Config, data::dataset::Dataset, tensor::{backend::Backend, DataSerialize, Tensor}};
+
+/// Maps pixels in [0, 1] to the values the networks work on, `(pixel - mean) / std` per channel.
+///
+/// Real images are encoded by the `ImageBatcher`. The generator's `tanh` output is read as pixels
+/// `(x + 1) / 2` and encoded the same way, so the discriminator sees both kinds in one range. The
+/// defaults map [0, 1] to [-1, 1], where the generated images need no change at all.
+#[derive(Config, Debug)]
+pub struct Normalization {
+    #[config(default = "[0.5, 0.5, 0.5]")]
+    pub mean: [f64; 3],
+    #[config(default = "[0.5, 0.5, 0.5]")]
+    pub std: [f64; 3],
+}
+
+impl Normalization {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.std.iter().any(|std| *std <= 0.0) {
+            return Err(format!("normalization std is {:?}, expected positive values", self.std));
+        }
+        Ok(())
+    }
+
+    /// Per-channel mean and standard deviation of the pixels of a baked dataset.
+    pub fn from_dataset<D: Dataset<DataSerialize<u8>>>(dataset: &D) -> Self {
+        let mut sums = [0.0f64; 3];
+        let mut squares = [0.0f64; 3];
+        let mut count = 0usize;
+        for item in dataset.iter() {
+            // Baked pixels are interleaved RGB.
+            for pixel in item.value.chunks_exact(3) {
+                for (channel, value) in pixel.iter().enumerate() {
+                    let value = *value as f64 / 255.0;
+                    sums[channel] += value;
+                    squares[channel] += value * value;
+                }
+            }
+            count += item.value.len() / 3;
+        }
+        assert!(count > 0, "Dataset should contain images to compute a normalization from");
+        let mean = sums.map(|sum| sum / count as f64);
+        let std = [0, 1, 2].map(|channel| (squares[channel] / count as f64 - mean[channel] * mean[channel]).max(0.0).sqrt().max(1e-3));
+        Self { mean, std }
+    }
+
+    fn channels<B: Backend>(values: [f64; 3], device: &B::Device) -> Tensor<B, 4> {
+        Tensor::<B, 1>::from_floats(values.map(|value| value as f32)).reshape([1, 3, 1, 1]).to_device(device)
+    }
+
+    /// Encodes pixels in [0, 1], `[batch, 3, height, width]`.
+    pub fn encode<B: Backend>(&self, pixels: Tensor<B, 4>) -> Tensor<B, 4> {
+        let device = pixels.device();
+        (pixels - Self::channels(self.mean, &device)) / Self::channels(self.std, &device)
+    }
+
+    /// Decodes images back to pixels, clamped to [0, 1] so out of range values saturate instead of wrapping.
+    pub fn decode<B: Backend>(&self, images: Tensor<B, 4>) -> Tensor<B, 4> {
+        let device = images.device();
+        (images * Self::channels(self.std, &device) + Self::channels(self.mean, &device)).clamp(0.0, 1.0)
+    }
+
+    /// Encodes the `tanh` output of the generator.
+    pub fn generated<B: Backend>(&self, images: Tensor<B, 4>) -> Tensor<B, 4> {
+        self.encode(images.add_scalar(1.0).div_scalar(2.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{backend::NdArray, data::dataset::InMemDataset, tensor::{Data, Shape}};
+
+    use super::*;
+
+    type TestBackend = NdArray<f32>;
+
+    fn assert_close(actual: Tensor<TestBackend, 4>, expected: &[f32]) {
+        for (actual, expected) in actual.into_data().value.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-5, "{actual} != {expected}");
+        }
+    }
+
+    fn normalization() -> Normalization {
+        Normalization::new().with_mean([0.4, 0.5, 0.6]).with_std([0.2, 0.25, 0.5])
+    }
+
+    #[test]
+    fn decode_inverts_encode() {
+        let values = [0.0, 0.1, 0.5, 0.9, 1.0, 0.3];
+        let pixels = Tensor::<TestBackend, 1>::from_floats(values).reshape([1, 3, 1, 2]);
+        assert_close(normalization().decode(normalization().encode(pixels)), &values);
+    }
+
+    #[test]
+    fn decode_saturates_out_of_range_values() {
+        let images = Tensor::<TestBackend, 1>::from_floats([-10.0, 10.0, -10.0, 10.0, -10.0, 10.0]).reshape([1, 3, 1, 2]);
+        assert_close(normalization().decode(images), &[0.0, 1.0, 0.0, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn default_leaves_generated_images_unchanged() {
+        let images = Tensor::<TestBackend, 1>::from_floats([-1.0, -0.5, 0.0, 0.5, 1.0, 0.25]).reshape([1, 3, 1, 2]);
+        assert_close(Normalization::new().generated(images), &[-1.0, -0.5, 0.0, 0.5, 1.0, 0.25]);
+    }
+
+    #[test]
+    fn from_dataset_computes_channel_statistics() {
+        // Two one pixel images: red is black in one and full in the other, green and blue are constant.
+        let images = [[0, 51, 255], [255, 51, 255]].map(|pixel| Data::new(pixel.to_vec(), Shape::new([1, 1, 3])).serialize());
+        let normalization = Normalization::from_dataset(&InMemDataset::new(images.to_vec()));
+        for (actual, expected) in normalization.mean.iter().zip([0.5, 0.2, 1.0]) {
+            assert!((actual - expected).abs() < 1e-9, "mean {actual} != {expected}");
+        }
+        for (actual, expected) in normalization.std.iter().zip([0.5, 1e-3, 1e-3]) {
+            assert!((actual - expected).abs() < 1e-9, "std {actual} != {expected}");
+        }
+    }
+}
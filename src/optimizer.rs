@@ -0,0 +1,181 @@
+use std::path::PathBuf;
+
+use burn::{config::Config, grad_clipping::GradientClippingConfig, module::AutodiffModule, optim::{decay::WeightDecayConfig, momentum::MomentumConfig, AdamConfig, AdamWConfig, GradientsParams, Optimizer, RMSPropConfig, SgdConfig}, record::{CompactRecorder, Recorder}, tensor::backend::AutodiffBackend};
+
+use crate::lr_schedule::LrSchedule;
+
+#[derive(Config, Debug)]
+pub enum OptimizerKind {
+    Adam,
+    /// Adam with decoupled weight decay.
+    AdamW,
+    /// Stochastic gradient descent, with momentum if `momentum` is set.
+    Sgd,
+    RmsProp,
+}
+
+/// Optimizer of one network.
+#[derive(Config, Debug)]
+pub struct OptimizerConfig {
+    #[config(default = "OptimizerKind::Adam")]
+    pub kind: OptimizerKind,
+    /// Base learning rate, the schedule scales it over the run.
+    #[config(default = 0.0002)]
+    pub learning_rate: f64,
+    #[config(default = "LrSchedule::Constant")]
+    pub schedule: LrSchedule,
+    /// Decay of the first moment estimate of Adam and AdamW.
+    #[config(default = 0.9)]
+    pub beta_1: f32,
+    /// Decay of the second moment estimate of Adam and AdamW.
+    #[config(default = 0.999)]
+    pub beta_2: f32,
+    /// Momentum of SGD and RMSProp, 0 turns it off.
+    #[config(default = 0.0)]
+    pub momentum: f64,
+    #[config(default = 0.0)]
+    pub weight_decay: f64,
+    /// Largest norm of the gradient of a parameter, larger gradients are scaled down to it.
+    pub grad_clip_norm: Option<f32>,
+}
+
+impl OptimizerConfig {
+    /// Checks that the settings describe an optimizer that can be built.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.learning_rate <= 0.0 {
+            return Err(format!("learning_rate is {}, expected a positive rate", self.learning_rate));
+        }
+        if !(0.0..1.0).contains(&self.beta_1) || !(0.0..1.0).contains(&self.beta_2) {
+            return Err(format!("betas are ({}, {}), expected values in [0, 1)", self.beta_1, self.beta_2));
+        }
+        if !(0.0..1.0).contains(&self.momentum) {
+            return Err(format!("momentum is {}, expected a value in [0, 1)", self.momentum));
+        }
+        if self.weight_decay < 0.0 {
+            return Err(format!("weight_decay is {}, expected a non-negative weight", self.weight_decay));
+        }
+        if self.grad_clip_norm.is_some_and(|norm| norm <= 0.0) {
+            return Err("grad_clip_norm has to be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Learning rate of step `step`, see `LrSchedule::learning_rate`.
+    pub fn learning_rate_at(&self, step: usize, steps_per_epoch: usize, num_epochs: usize) -> f64 {
+        self.schedule.learning_rate(self.learning_rate, step, steps_per_epoch, num_epochs)
+    }
+
+    /// Builds the optimizer, continuing from the record at `record_path` if one is given.
+    pub fn init<M, B>(&self, record_path: Option<PathBuf>) -> Box<dyn ModuleOptimizer<M, B>>
+    where
+        B: AutodiffBackend,
+        M: AutodiffModule<B> + 'static,
+    {
+        let weight_decay = (self.weight_decay > 0.0).then(|| WeightDecayConfig::new(self.weight_decay));
+        let grad_clipping = self.grad_clip_norm.map(GradientClippingConfig::Norm);
+        match self.kind {
+            OptimizerKind::Adam => with_record(
+                AdamConfig::new().with_beta_1(self.beta_1).with_beta_2(self.beta_2).with_weight_decay(weight_decay).with_grad_clipping(grad_clipping).init(),
+                record_path,
+            ),
+            OptimizerKind::AdamW => with_record(
+                AdamWConfig::new().with_beta_1(self.beta_1).with_beta_2(self.beta_2).with_weight_decay(self.weight_decay as f32).with_grad_clipping(grad_clipping).init(),
+                record_path,
+            ),
+            OptimizerKind::Sgd => with_record(
+                SgdConfig::new()
+                    .with_momentum((self.momentum > 0.0).then(|| MomentumConfig::new().with_momentum(self.momentum).with_dampening(0.0)))
+                    .with_weight_decay(weight_decay)
+                    .with_gradient_clipping(grad_clipping)
+                    .init(),
+                record_path,
+            ),
+            OptimizerKind::RmsProp => with_record(
+                RMSPropConfig::new().with_momentum(self.momentum as f32).with_weight_decay(weight_decay).with_grad_clipping(grad_clipping).init(),
+                record_path,
+            ),
+        }
+    }
+}
+
+/// The part of `Optimizer` the training loop uses, without the associated record type so it can be boxed.
+pub trait ModuleOptimizer<M, B: AutodiffBackend> {
+    fn step(&mut self, learning_rate: f64, module: M, grads: GradientsParams) -> M;
+    fn save(&self, path: PathBuf);
+}
+
+impl<O, M, B> ModuleOptimizer<M, B> for O
+where
+    B: AutodiffBackend,
+    M: AutodiffModule<B>,
+    O: Optimizer<M, B>,
+{
+    fn step(&mut self, learning_rate: f64, module: M, grads: GradientsParams) -> M {
+        Optimizer::step(self, learning_rate, module, grads)
+    }
+
+    fn save(&self, path: PathBuf) {
+        CompactRecorder::new()
+            .record(self.to_record(), path)
+            .expect("Optimizer should be saved successfully");
+    }
+}
+
+fn with_record<O, M, B>(optimizer: O, record_path: Option<PathBuf>) -> Box<dyn ModuleOptimizer<M, B>>
+where
+    B: AutodiffBackend,
+    M: AutodiffModule<B>,
+    O: Optimizer<M, B> + 'static,
+{
+    let optimizer = match record_path {
+        Some(path) => optimizer.load_record(CompactRecorder::new().load(path).expect("Optimizer record should be loaded successfully")),
+        None => optimizer,
+    };
+    Box::new(optimizer)
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{backend::{Autodiff, NdArray}, nn::{Linear, LinearConfig}, tensor::Tensor};
+
+    use super::*;
+
+    type TestBackend = Autodiff<NdArray<f32>>;
+
+    /// Weights of a linear layer after one step of `config` on a loss whose gradient is 1 for every weight.
+    fn step_once(config: &OptimizerConfig) -> (Tensor<TestBackend, 2>, Tensor<TestBackend, 2>) {
+        let linear: Linear<TestBackend> = LinearConfig::new(4, 4).init();
+        let before = linear.weight.val();
+        let loss = linear.forward(Tensor::ones([1, 4])).sum();
+        let grads = GradientsParams::from_grads(loss.backward(), &linear);
+        let mut optimizer = config.init::<Linear<TestBackend>, TestBackend>(None);
+        let linear = optimizer.step(config.learning_rate, linear, grads);
+        (before, linear.weight.val())
+    }
+
+    #[test]
+    fn every_kind_steps_against_the_gradient() {
+        for kind in [OptimizerKind::Adam, OptimizerKind::AdamW, OptimizerKind::Sgd, OptimizerKind::RmsProp] {
+            let config = OptimizerConfig::new().with_kind(kind.clone()).with_learning_rate(0.01);
+            let (before, after) = step_once(&config);
+            let largest_change = (after - before).max().into_scalar();
+            assert!(largest_change < 0.0, "{kind:?} moved a weight by {largest_change}");
+        }
+    }
+
+    #[test]
+    fn grad_clip_norm_bounds_the_step() {
+        let config = OptimizerConfig::new().with_kind(OptimizerKind::Sgd).with_learning_rate(1.0).with_grad_clip_norm(Some(0.5));
+        let (before, after) = step_once(&config);
+        let change = after - before;
+        let distance = change.clone().mul(change).sum().sqrt().into_scalar();
+        assert!((distance - 0.5).abs() < 1e-4, "step of length {distance}, expected 0.5");
+    }
+
+    #[test]
+    fn learning_rate_at_scales_base_rate_by_schedule() {
+        let config = OptimizerConfig::new().with_learning_rate(0.001).with_schedule(LrSchedule::Warmup { steps: 4 });
+        assert!((config.learning_rate_at(1, 10, 2) - 0.0005).abs() < 1e-12);
+        assert!((config.learning_rate_at(10, 10, 2) - 0.001).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,100 @@
+use burn::tensor::{backend::AutodiffBackend, Distribution, Tensor};
+
+use crate::models::Discriminator;
+
+/// Distance of the two discriminator evaluations around each image in `input_gradient_norm`.
+const FINITE_DIFFERENCE_STEP: f64 = 1e-2;
+
+/// Per-image norm of the discriminator's gradient with respect to its input, `[batch]`.
+///
+/// Burn has no double backward, so the norm can not be differentiated directly. Instead the gradient
+/// direction `u` is computed in a separate, detached backward pass and the norm is estimated as the
+/// central difference `(D(x + h u) - D(x - h u)) / 2h`. That estimate is an ordinary forward pass and
+/// can be backpropagated to the discriminator parameters. Keeping `u` fixed does not bias those
+/// gradients to first order, since the directional derivative along the gradient is the norm itself.
+///
+/// The images of a batch are treated independently, so the discriminator should not use batch norm.
+pub fn input_gradient_norm<B: AutodiffBackend>(discriminator: &Discriminator<B>, images: Tensor<B, 4>) -> Tensor<B, 1> {
+    let [batch_size, _, _, _] = images.dims();
+
+    let inputs = images.clone().detach().require_grad();
+    let grads = discriminator.forward(inputs.clone()).sum().backward();
+    let gradient = inputs.grad(&grads).expect("Discriminator output should depend on its input");
+
+    let norm = gradient.clone().powf(2.0).sum_dim(1).sum_dim(2).sum_dim(3).sqrt().clamp_min(1e-12);
+    let direction = Tensor::<B, 4>::from_inner(gradient / norm).mul_scalar(FINITE_DIFFERENCE_STEP);
+
+    let ahead = discriminator.forward(images.clone() + direction.clone());
+    let behind = discriminator.forward(images - direction);
+    (ahead - behind).div_scalar(2.0 * FINITE_DIFFERENCE_STEP).reshape([batch_size])
+}
+
+/// WGAN-GP penalty `mean((|grad D(x)| - 1)^2)` at random interpolations between real and generated images.
+pub fn gradient_penalty<B: AutodiffBackend>(discriminator: &Discriminator<B>, real_images: Tensor<B, 4>, fake_images: Tensor<B, 4>) -> Tensor<B, 1> {
+    let [batch_size, _, _, _] = real_images.dims();
+    let mix = Tensor::<B, 4>::random([batch_size, 1, 1, 1], Distribution::Uniform(0.0, 1.0)).to_device(&real_images.device());
+    let interpolated = real_images.detach() * mix.clone() + fake_images.detach() * (mix.neg() + 1.0);
+
+    (input_gradient_norm(discriminator, interpolated) - 1.0).powf(2.0).mean()
+}
+
+/// Zero-centered penalty `mean(|grad D(x)|^2)` at `images`, R1 on real and R2 on generated images.
+pub fn zero_centered_gradient_penalty<B: AutodiffBackend>(discriminator: &Discriminator<B>, images: Tensor<B, 4>) -> Tensor<B, 1> {
+    input_gradient_norm(discriminator, images.detach()).powf(2.0).mean()
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{backend::{Autodiff, NdArray}, nn::Initializer, optim::GradientsParams, tensor::backend::Backend};
+
+    use super::*;
+    use crate::models::{DiscriminatorConfig, Norm};
+
+    type TestBackend = Autodiff<NdArray<f32>>;
+
+    fn discriminator() -> Discriminator<TestBackend> {
+        TestBackend::seed(0);
+        DiscriminatorConfig::new()
+            .with_feature_map_size(4)
+            .with_image_size(32)
+            .with_norm(Norm::None)
+            .init(&Initializer::Normal { mean: 0.0, std: 0.2 })
+    }
+
+    /// Norm of the exact input gradient of each image, from one backward pass per image.
+    fn analytic_gradient_norms(discriminator: &Discriminator<TestBackend>, images: Tensor<TestBackend, 4>) -> Vec<f32> {
+        let [batch_size, channels, height, width] = images.dims();
+        (0..batch_size)
+            .map(|index| {
+                let image = images.clone().slice([index..index + 1, 0..channels, 0..height, 0..width]).detach().require_grad();
+                let grads = discriminator.forward(image.clone()).sum().backward();
+                let gradient = image.grad(&grads).expect("Discriminator output should depend on its input");
+                gradient.powf(2.0).sum().sqrt().into_scalar()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimate_matches_analytic_gradient_norm() {
+        let discriminator = discriminator();
+        let images = Tensor::<TestBackend, 4>::random([3, 3, 32, 32], Distribution::Uniform(-1.0, 1.0));
+
+        let estimate = input_gradient_norm(&discriminator, images.clone()).into_data().convert::<f32>().value;
+        let analytic = analytic_gradient_norms(&discriminator, images);
+        for (estimate, analytic) in estimate.iter().zip(&analytic) {
+            assert!(*analytic > 0.0, "The test discriminator should have a non-zero input gradient");
+            assert!((estimate - analytic).abs() <= 0.02 * analytic, "estimate {estimate} differs from analytic norm {analytic}");
+        }
+    }
+
+    #[test]
+    fn estimate_is_differentiable_in_the_parameters() {
+        let discriminator = discriminator();
+        let images = Tensor::<TestBackend, 4>::random([2, 3, 32, 32], Distribution::Uniform(-1.0, 1.0));
+
+        let penalty_grads = GradientsParams::from_grads(zero_centered_gradient_penalty(&discriminator, images.clone()).backward(), &discriminator);
+        let output_grads = GradientsParams::from_grads(discriminator.forward(images).sum().backward(), &discriminator);
+        assert!(!penalty_grads.is_empty());
+        assert_eq!(penalty_grads.len(), output_grads.len(), "Penalty should reach every parameter the output depends on");
+    }
+}
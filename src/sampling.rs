@@ -0,0 +1,57 @@
+use burn::{config::Config, module::Module, nn::Initializer, tensor::{backend::Backend, Distribution, Tensor}};
+
+use chrono::Local;
+
+use crate::{checkpoint::{latest_checkpoint, load_generator, load_generator_ema}, image::{image_grid, nth_image, tensor_to_image, to_channels_last}, training::TrainingConfig};
+
+/// How the generated images are written to disk.
+pub enum SampleOutput {
+    /// One PNG per image.
+    Individual { count: usize },
+    /// A single contact sheet with `rows` x `columns` images.
+    Grid { rows: usize, columns: usize },
+}
+
+/// Generates images with the generator of checkpoint `checkpoint` (or the latest one) in `artifact_dir`.
+///
+/// With `use_ema` the moving average of the generator is used if the checkpoint has one.
+/// Should be called with a backend without autodiff, so batch norm and dropout run in inference mode.
+pub fn sample<B: Backend>(artifact_dir: &str, checkpoint: Option<String>, use_ema: bool, output: SampleOutput, seed: u64, output_dir: &str, device: B::Device) {
+    let config = TrainingConfig::load(format!("{artifact_dir}/config.json"))
+        .unwrap_or_else(|err| panic!("Training config of {artifact_dir} should be readable: {err}"));
+    let checkpoint = checkpoint
+        .or_else(|| latest_checkpoint(artifact_dir))
+        .unwrap_or_else(|| panic!("No checkpoint found in {artifact_dir}"));
+
+    // The initializer does not matter, every parameter is overwritten by the record.
+    let generator = config.generator.init::<B>(&Initializer::Zeros);
+    let average = if use_ema { load_generator_ema(artifact_dir, &checkpoint, generator.clone()) } else { None };
+    if use_ema && average.is_none() {
+        println!("Checkpoint {checkpoint} has no generator average, sampling from the generator.");
+    }
+    let generator = average
+        .unwrap_or_else(|| load_generator(artifact_dir, &checkpoint, generator))
+        .to_device(&device);
+
+    let count = match output {
+        SampleOutput::Individual { count } => count,
+        SampleOutput::Grid { rows, columns } => rows * columns,
+    };
+    B::seed(seed);
+    let latents = Tensor::<B, 2>::random([count, config.generator.latent_vector_size], Distribution::Normal(0.0, 1.0)).to_device(&device);
+    let images = config.normalization.decode(config.normalization.generated(generator.forward(latents)));
+
+    std::fs::create_dir_all(output_dir).expect("Output directory should be created successfully");
+    match output {
+        SampleOutput::Individual { count } => {
+            for index in 0..count {
+                let image = nth_image(images.clone(), index);
+                tensor_to_image(&format!("{output_dir}/sample-{seed}-{index}.png"), to_channels_last(image));
+            }
+        }
+        SampleOutput::Grid { rows, columns } => {
+            tensor_to_image(&format!("{output_dir}/grid-{seed}-{rows}x{columns}.png"), image_grid(images, columns));
+        }
+    }
+    println!("[{}]: Wrote {count} samples of checkpoint {checkpoint} to {output_dir}", Local::now());
+}
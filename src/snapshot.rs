@@ -0,0 +1,100 @@
+use std::{fs::{self, File}, path::Path};
+
+use ::image::{codecs::gif::{GifEncoder, Repeat}, Delay, DynamicImage, Frame};
+use burn::{config::Config, tensor::{backend::Backend, Data, Distribution, Shape, Tensor}};
+
+use crate::{image::{image_grid, tensor_to_image}, models::Generator, normalization::Normalization};
+
+/// Progress snapshots: a grid of images from the same latents, written while training.
+#[derive(Config, Debug)]
+pub struct SnapshotConfig {
+    /// Steps between two snapshots, 0 turns them off.
+    #[config(default = 100)]
+    pub interval: usize,
+    /// The grid has `grid_size` x `grid_size` images.
+    #[config(default = 4)]
+    pub grid_size: usize,
+    /// Seed of the latents of the grid.
+    #[config(default = 0)]
+    pub seed: u64,
+    /// Assemble all snapshots into `timelapse.gif` when training ends.
+    #[config(default = true)]
+    pub timelapse: bool,
+    /// Time every snapshot is shown in the timelapse.
+    #[config(default = 200)]
+    pub frame_delay_ms: u32,
+}
+
+/// The latents of the snapshot grid, saved with the run so every snapshot and resume uses the same ones.
+#[derive(Config)]
+struct SnapshotLatents {
+    count: usize,
+    latent_vector_size: usize,
+    values: Vec<f32>,
+}
+
+fn snapshot_dir(artifact_dir: &str) -> String {
+    format!("{artifact_dir}/snapshots")
+}
+
+impl SnapshotConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.grid_size == 0 {
+            return Err("snapshot grid_size has to be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Loads the latents of the run in `artifact_dir`, drawing and saving them on the first call.
+    ///
+    /// Reseeds the backend, so it should run before the training loop seeds its steps.
+    pub fn latents<B: Backend>(&self, artifact_dir: &str, latent_vector_size: usize, device: &B::Device) -> Tensor<B, 2> {
+        let count = self.grid_size * self.grid_size;
+        let path = format!("{}/latents.json", snapshot_dir(artifact_dir));
+        let latents = match SnapshotLatents::load(&path) {
+            Ok(latents) if latents.count == count && latents.latent_vector_size == latent_vector_size => latents,
+            _ => {
+                B::seed(self.seed);
+                let values = Tensor::<B, 2>::random([count, latent_vector_size], Distribution::Normal(0.0, 1.0)).into_data().convert::<f32>().value;
+                let latents = SnapshotLatents::new(count, latent_vector_size, values);
+                fs::create_dir_all(snapshot_dir(artifact_dir)).expect("Snapshot directory should be created successfully");
+                latents.save(&path).expect("Snapshot latents should be saved successfully");
+                latents
+            }
+        };
+        Tensor::from_data(Data::new(latents.values, Shape::new([count, latent_vector_size])).convert()).to_device(device)
+    }
+
+    /// Writes the grid of `generator` for `latents` as the snapshot of step `global_step`.
+    pub fn write<B: Backend>(&self, artifact_dir: &str, generator: &Generator<B>, latents: Tensor<B, 2>, normalization: &Normalization, global_step: usize) {
+        let images = normalization.decode(normalization.generated(generator.forward(latents)));
+        tensor_to_image(&format!("{}/step-{global_step:08}.png", snapshot_dir(artifact_dir)), image_grid(images, self.grid_size));
+    }
+
+    /// Assembles the snapshots of the run in `artifact_dir` into `timelapse.gif`, in step order.
+    pub fn write_timelapse(&self, artifact_dir: &str) {
+        let mut snapshots: Vec<_> = match fs::read_dir(snapshot_dir(artifact_dir)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("step-") && name.ends_with(".png")))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        if snapshots.is_empty() {
+            return;
+        }
+        // The zero padded step keeps the names in step order.
+        snapshots.sort();
+
+        let path = Path::new(artifact_dir).join("timelapse.gif");
+        let mut encoder = GifEncoder::new(File::create(&path).expect("Timelapse should be created successfully"));
+        encoder.set_repeat(Repeat::Infinite).expect("Timelapse should be written successfully");
+        for snapshot in &snapshots {
+            let frame = ::image::open(snapshot).unwrap_or_else(|err| panic!("Snapshot {} should be readable: {err}", snapshot.display()));
+            let frame = Frame::from_parts(DynamicImage::into_rgba8(frame), 0, 0, Delay::from_numer_denom_ms(self.frame_delay_ms, 1));
+            encoder.encode_frame(frame).expect("Timelapse should be written successfully");
+        }
+        println!("Wrote a timelapse of {} snapshots to {}", snapshots.len(), path.display());
+    }
+}
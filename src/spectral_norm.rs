@@ -0,0 +1,273 @@
+use burn::{module::{Module, Param, RunningState}, nn::{conv::{Conv2d, Conv2dConfig, ConvTranspose2d, ConvTranspose2dConfig}, Linear, LinearConfig, PaddingConfig2d}, tensor::{backend::Backend, module::{conv2d, conv_transpose2d}, ops::{ConvOptions, ConvTransposeOptions}, Distribution, Tensor}};
+
+/// Keeps the vector normalizations away from a division by zero.
+const EPSILON: f64 = 1e-12;
+
+/// A weight divided by an estimate of its largest singular value, see https://arxiv.org/abs/1802.05957
+///
+/// The weight is viewed as a matrix `[dim, all other dims]`, where `dim` indexes the output channels as
+/// in PyTorch. Every training forward pass runs one step of power iteration from the left singular vector
+/// `u`, which is persisted in the record, so the estimate converges over the course of training. Inference
+/// uses the stored `u` without updating it.
+#[derive(Module, Debug)]
+pub struct SpectralNorm<B: Backend, const D: usize> {
+    weight: Param<Tensor<B, D>>,
+    u: RunningState<Tensor<B, 1>>,
+    dim: usize,
+}
+
+fn normalize<B: Backend>(vector: Tensor<B, 1>) -> Tensor<B, 1> {
+    let norm = vector.clone().powf(2.0).sum().sqrt().add_scalar(EPSILON);
+    vector / norm
+}
+
+impl<B: Backend, const D: usize> SpectralNorm<B, D> {
+    fn new(weight: Param<Tensor<B, D>>, dim: usize) -> Self {
+        let rows = weight.dims()[dim];
+        let u = Tensor::random([rows], Distribution::Normal(0.0, 1.0)).to_device(&weight.device());
+        Self { weight, u: RunningState::new(normalize(u)), dim }
+    }
+
+    /// The normalized weight.
+    pub fn weight(&self) -> Tensor<B, D> {
+        let weight = self.weight.val();
+        let shape = weight.shape();
+        let rows = shape.dims[self.dim];
+        let columns = shape.num_elements() / rows;
+        let matrix = weight.clone().swap_dims(0, self.dim).reshape([rows, columns]);
+
+        // The singular vectors are constants for the gradient, as in the reference implementation.
+        let detached = matrix.clone().detach();
+        let mut u = if B::ad_enabled() { self.u.value_sync() } else { self.u.value() };
+        let v = normalize(detached.clone().transpose().matmul(u.clone().reshape([rows, 1])).reshape([columns]));
+        if B::ad_enabled() {
+            u = normalize(detached.matmul(v.clone().reshape([columns, 1])).reshape([rows]));
+            self.u.update(u.clone().detach());
+        }
+
+        let sigma = (u * matrix.matmul(v.reshape([columns, 1])).reshape([rows])).sum();
+        weight / sigma.reshape([1; D])
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct SpectralConv2d<B: Backend> {
+    weight: SpectralNorm<B, 4>,
+    bias: Option<Param<Tensor<B, 1>>>,
+    stride: [usize; 2],
+    padding: [usize; 2],
+    dilation: [usize; 2],
+    groups: usize,
+}
+
+impl<B: Backend> SpectralConv2d<B> {
+    /// Builds the layer `config` describes, initialized like a plain `Conv2d`.
+    pub fn new(config: &Conv2dConfig) -> Self {
+        let padding = match config.padding {
+            PaddingConfig2d::Valid => [0, 0],
+            PaddingConfig2d::Explicit(height, width) => [height, width],
+            PaddingConfig2d::Same => panic!("Spectral normalized convolutions need explicit padding"),
+        };
+        let record = config.init::<B>().into_record();
+        Self {
+            weight: SpectralNorm::new(record.weight, 0),
+            bias: record.bias,
+            stride: config.stride,
+            padding,
+            dilation: config.dilation,
+            groups: config.groups,
+        }
+    }
+
+    pub fn forward(&self, input: Tensor<B, 4>) -> Tensor<B, 4> {
+        conv2d(input, self.weight.weight(), self.bias.as_ref().map(|bias| bias.val()), ConvOptions::new(self.stride, self.padding, self.dilation, self.groups))
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct SpectralConvTranspose2d<B: Backend> {
+    weight: SpectralNorm<B, 4>,
+    bias: Option<Param<Tensor<B, 1>>>,
+    stride: [usize; 2],
+    padding: [usize; 2],
+    padding_out: [usize; 2],
+    dilation: [usize; 2],
+    groups: usize,
+}
+
+impl<B: Backend> SpectralConvTranspose2d<B> {
+    /// Builds the layer `config` describes, initialized like a plain `ConvTranspose2d`.
+    pub fn new(config: &ConvTranspose2dConfig) -> Self {
+        let record = config.init::<B>().into_record();
+        Self {
+            // The weight is `[in, out / groups, k, k]`, the output channels are its second dim.
+            weight: SpectralNorm::new(record.weight, 1),
+            bias: record.bias,
+            stride: config.stride,
+            padding: config.padding,
+            padding_out: config.padding_out,
+            dilation: config.dilation,
+            groups: config.groups,
+        }
+    }
+
+    pub fn forward(&self, input: Tensor<B, 4>) -> Tensor<B, 4> {
+        conv_transpose2d(
+            input,
+            self.weight.weight(),
+            self.bias.as_ref().map(|bias| bias.val()),
+            ConvTransposeOptions::new(self.stride, self.padding, self.padding_out, self.dilation, self.groups),
+        )
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct SpectralLinear<B: Backend> {
+    weight: SpectralNorm<B, 2>,
+    bias: Option<Param<Tensor<B, 1>>>,
+}
+
+impl<B: Backend> SpectralLinear<B> {
+    /// Builds the layer `config` describes, initialized like a plain `Linear`.
+    pub fn new(config: &LinearConfig) -> Self {
+        let record = config.init::<B>().into_record();
+        // A matrix and its transpose share their singular values, so the `[in, out]` layout needs no swap.
+        Self { weight: SpectralNorm::new(record.weight, 0), bias: record.bias }
+    }
+
+    pub fn forward(&self, input: Tensor<B, 2>) -> Tensor<B, 2> {
+        let output = input.matmul(self.weight.weight());
+        match &self.bias {
+            Some(bias) => output + bias.val().unsqueeze(),
+            None => output,
+        }
+    }
+}
+
+// The layers below hold either the plain or the spectral normalized variant, so one network type covers both.
+
+#[derive(Module, Debug)]
+pub struct Conv2dLayer<B: Backend> {
+    plain: Option<Conv2d<B>>,
+    spectral: Option<SpectralConv2d<B>>,
+}
+
+impl<B: Backend> Conv2dLayer<B> {
+    pub fn new(config: &Conv2dConfig, spectral: bool) -> Self {
+        if spectral {
+            Self { plain: None, spectral: Some(SpectralConv2d::new(config)) }
+        } else {
+            Self { plain: Some(config.init()), spectral: None }
+        }
+    }
+
+    pub fn forward(&self, input: Tensor<B, 4>) -> Tensor<B, 4> {
+        match (&self.plain, &self.spectral) {
+            (Some(layer), _) => layer.forward(input),
+            (None, Some(layer)) => layer.forward(input),
+            (None, None) => unreachable!("Conv2dLayer always holds a layer"),
+        }
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct ConvTranspose2dLayer<B: Backend> {
+    plain: Option<ConvTranspose2d<B>>,
+    spectral: Option<SpectralConvTranspose2d<B>>,
+}
+
+impl<B: Backend> ConvTranspose2dLayer<B> {
+    pub fn new(config: &ConvTranspose2dConfig, spectral: bool) -> Self {
+        if spectral {
+            Self { plain: None, spectral: Some(SpectralConvTranspose2d::new(config)) }
+        } else {
+            Self { plain: Some(config.init()), spectral: None }
+        }
+    }
+
+    pub fn forward(&self, input: Tensor<B, 4>) -> Tensor<B, 4> {
+        match (&self.plain, &self.spectral) {
+            (Some(layer), _) => layer.forward(input),
+            (None, Some(layer)) => layer.forward(input),
+            (None, None) => unreachable!("ConvTranspose2dLayer always holds a layer"),
+        }
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct LinearLayer<B: Backend> {
+    plain: Option<Linear<B>>,
+    spectral: Option<SpectralLinear<B>>,
+}
+
+impl<B: Backend> LinearLayer<B> {
+    pub fn new(config: &LinearConfig, spectral: bool) -> Self {
+        if spectral {
+            Self { plain: None, spectral: Some(SpectralLinear::new(config)) }
+        } else {
+            Self { plain: Some(config.init()), spectral: None }
+        }
+    }
+
+    pub fn forward(&self, input: Tensor<B, 2>) -> Tensor<B, 2> {
+        match (&self.plain, &self.spectral) {
+            (Some(layer), _) => layer.forward(input),
+            (None, Some(layer)) => layer.forward(input),
+            (None, None) => unreachable!("LinearLayer always holds a layer"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{backend::{Autodiff, NdArray}, module::AutodiffModule, record::{BinBytesRecorder, FullPrecisionSettings, Recorder}};
+
+    use super::*;
+
+    type TestBackend = Autodiff<NdArray<f32>>;
+
+    /// Largest singular value of `matrix`, by running power iteration to convergence.
+    fn largest_singular_value(matrix: Tensor<NdArray<f32>, 2>) -> f32 {
+        let columns = matrix.dims()[1];
+        let mut v = normalize(Tensor::ones([columns]));
+        for _ in 0..500 {
+            let u = matrix.clone().matmul(v.reshape([columns, 1]));
+            v = normalize(matrix.clone().transpose().matmul(u).reshape([columns]));
+        }
+        matrix.matmul(v.reshape([columns, 1])).powf(2.0).sum().sqrt().into_scalar()
+    }
+
+    fn spectral_norm(dim: usize) -> SpectralNorm<TestBackend, 4> {
+        SpectralNorm::new(Param::from(Tensor::random([6, 4, 3, 3], Distribution::Normal(0.0, 1.0))), dim)
+    }
+
+    #[test]
+    fn normalized_weight_has_unit_spectral_norm() {
+        for dim in [0, 1] {
+            let norm = spectral_norm(dim);
+            for _ in 0..20 {
+                norm.weight();
+            }
+            let weight = norm.weight().inner().swap_dims(0, dim);
+            let [rows, a, b, c] = weight.dims();
+            let sigma = largest_singular_value(weight.reshape([rows, a * b * c]));
+            assert!((sigma - 1.0).abs() < 1e-2, "spectral norm over dim {dim} is {sigma}");
+        }
+    }
+
+    #[test]
+    fn u_round_trips_through_the_record() {
+        let norm = spectral_norm(1);
+        for _ in 0..3 {
+            norm.weight();
+        }
+        let recorder = BinBytesRecorder::<FullPrecisionSettings>::default();
+        let bytes = recorder.record(norm.clone().into_record(), ()).unwrap();
+        let loaded = spectral_norm(1).load_record(recorder.load(bytes).unwrap());
+
+        assert_eq!(loaded.u.value_sync().dims(), [4]);
+        assert_eq!(loaded.u.value_sync().into_data(), norm.u.value_sync().into_data());
+        let loaded_inference = loaded.valid().weight().into_data();
+        norm.valid().weight().into_data().assert_approx_eq(&loaded_inference, 5);
+    }
+}
@@ -1,12 +1,11 @@
 use std::time::{Duration, Instant};
 
-use burn::{config::Config, optim::{AdamConfig, GradientsParams, GradientsAccumulator, Optimizer, SgdConfig}, tensor::{backend::{AutodiffBackend, Backend}, Data, Tensor, ops::TensorOps, Float, Int, Distribution}, data::{dataloader::{self, DataLoaderBuilder}, dataset::SqliteDataset, self}, nn::loss::{CrossEntropyLoss, BinaryCrossEntropyLoss, BinaryCrossEntropyLossConfig}, record::CompactRecorder};
-use burn::module::Module;
-use image::Rgb;
+use burn::{config::Config, module::AutodiffModule, optim::GradientsParams, tensor::{activation::sigmoid, backend::{AutodiffBackend, Backend}, ElementConversion, Tensor, Float, Distribution}, data::{dataloader::DataLoaderBuilder, dataset::SqliteDataset}};
 
+use burn::{data::dataset::Dataset, tensor::DataSerialize};
 use chrono::Local;
 
-use crate::{models::{GeneratorConfig, DiscriminatorConfig, Discriminator}, data_loader::{ImageBatcher, make_image_dataset}, image::{IMAGE_HEIGHT, IMAGE_WIDTH}, cross_entropy_loss::cross_entropy_loss};
+use crate::{augment::AugmentConfig, balance::{BalanceConfig, RunningDiscriminator}, checkpoint::{checkpoint_tag, latest_checkpoint, CheckpointConfig, load_checkpoint, load_generator_ema, load_state, optimizer_path, save_checkpoint, TrainingState}, ema::{update_average, warmup_decay}, models::{GeneratorConfig, DiscriminatorConfig, Discriminator, Generator, Norm}, data_loader::{ImageBatcher, make_image_dataset, TRAIN_SPLIT, VALID_SPLIT}, normalization::Normalization, snapshot::SnapshotConfig, gan_loss::GanLoss, instance_noise::InstanceNoiseConfig, labels::LabelConfig, optimizer::{OptimizerConfig, OptimizerKind}, regularization::{gradient_penalty, zero_centered_gradient_penalty}};
 
 
 
@@ -18,40 +17,199 @@ pub struct TrainingConfig {
     pub num_epochs: usize,
     #[config(default = 128)]
     pub batch_size: usize,
+    /// Skip the last batch of an epoch when it is smaller than `batch_size`.
+    #[config(default = false)]
+    pub drop_last: bool,
+    /// Threads loading batches. With more than one the batch order depends on thread timing,
+    /// so a resumed run only sees the exact batches of the interrupted one with at most one worker.
     #[config(default = 2)]
     pub num_workers: usize,
     #[config(default = 42)]
     pub seed: u64,
-    #[config(default = 0.0002)]
-    pub learning_rate: f64,
+    #[config(default = "OptimizerConfig::new().with_beta_1(0.5)")]
+    pub generator_optimizer: OptimizerConfig,
+    #[config(default = "OptimizerConfig::new().with_kind(OptimizerKind::Sgd)")]
+    pub discriminator_optimizer: OptimizerConfig,
+    #[config(default = "GanLoss::NonSaturating")]
+    pub loss: GanLoss,
+    /// Weight of the WGAN-GP gradient penalty on the discriminator, 0 turns it off.
+    #[config(default = 0.0)]
+    pub gradient_penalty_weight: f64,
+    /// Discriminator updates per generator update.
+    #[config(default = 1)]
+    pub critic_iterations: usize,
+    /// Weight gamma of the R1 penalty `gamma / 2 * |grad D(x)|^2` on real images, 0 turns it off.
+    #[config(default = 0.0)]
+    pub r1_weight: f64,
+    /// Weight gamma of the R2 penalty `gamma / 2 * |grad D(G(z))|^2` on generated images, 0 turns it off.
+    #[config(default = 0.0)]
+    pub r2_weight: f64,
+    /// Discriminator updates between two evaluations of the R1 and R2 penalties.
+    #[config(default = 16)]
+    pub regularization_interval: usize,
+    /// Decay of the moving average of the generator weights per generator update.
+    #[config(default = 0.999)]
+    pub ema_decay: f64,
+    /// Steps at the start of training during which the moving average just copies the generator.
+    #[config(default = 1000)]
+    pub ema_warmup_steps: usize,
+    #[config(default = "LabelConfig::new()")]
+    pub labels: LabelConfig,
+    #[config(default = "InstanceNoiseConfig::new()")]
+    pub instance_noise: InstanceNoiseConfig,
+    #[config(default = "SnapshotConfig::new()")]
+    pub snapshot: SnapshotConfig,
+    #[config(default = "CheckpointConfig::new()")]
+    pub checkpoint: CheckpointConfig,
+    /// Pixel encoding of the real and generated images the discriminator sees.
+    #[config(default = "Normalization::new()")]
+    pub normalization: Normalization,
+    /// Differentiable augmentation of the discriminator inputs, `None` turns it off.
+    pub augment: Option<AugmentConfig>,
+    /// Adaptive number of discriminator updates per step, `None` always updates it once.
+    pub balance: Option<BalanceConfig>,
 }
 
-pub fn train<B: AutodiffBackend>(artifact_dir: &str, config: TrainingConfig, device: B::Device) {
-    println!("Starting Training Setup.");
+impl TrainingConfig {
+    /// Checks that the settings describe a training run that can be started.
+    pub fn validate(&self) -> Result<(), String> {
+        self.generator.validate()?;
+        self.discriminator.validate()?;
+        if self.generator.image_size != self.discriminator.image_size {
+            return Err(format!(
+                "generator image_size ({}) and discriminator image_size ({}) have to match",
+                self.generator.image_size, self.discriminator.image_size
+            ));
+        }
+        self.generator_optimizer.validate().map_err(|err| format!("generator_optimizer: {err}"))?;
+        self.discriminator_optimizer.validate().map_err(|err| format!("discriminator_optimizer: {err}"))?;
+        self.generator_optimizer.schedule.validate(self.generator_optimizer.learning_rate, self.num_epochs).map_err(|err| format!("generator_optimizer: {err}"))?;
+        self.discriminator_optimizer.schedule.validate(self.discriminator_optimizer.learning_rate, self.num_epochs).map_err(|err| format!("discriminator_optimizer: {err}"))?;
+        if self.critic_iterations == 0 {
+            return Err("critic_iterations has to be at least 1".to_string());
+        }
+        for (name, weight) in [("gradient_penalty_weight", self.gradient_penalty_weight), ("r1_weight", self.r1_weight), ("r2_weight", self.r2_weight)] {
+            if weight < 0.0 {
+                return Err(format!("{name} is {weight}, expected a non-negative weight"));
+            }
+        }
+        // The penalty estimates per-image input gradients, which batch norm mixes across the batch.
+        if matches!(self.discriminator.norm, Norm::Batch) {
+            for (name, weight) in [("gradient_penalty_weight", self.gradient_penalty_weight), ("r1_weight", self.r1_weight), ("r2_weight", self.r2_weight)] {
+                if weight > 0.0 {
+                    return Err(format!("{name} needs a discriminator without batch norm, use norm None or Spectral"));
+                }
+            }
+        }
+        if self.regularization_interval == 0 {
+            return Err("regularization_interval has to be at least 1".to_string());
+        }
+        self.normalization.validate()?;
+        self.snapshot.validate()?;
+        self.checkpoint.validate()?;
+        self.labels.validate()?;
+        self.instance_noise.validate()?;
+        if let Some(augment) = &self.augment {
+            augment.validate()?;
+        }
+        if let Some(balance) = &self.balance {
+            balance.validate()?;
+        }
+        if !(0.0..1.0).contains(&self.ema_decay) {
+            return Err(format!("ema_decay is {}, expected a value in [0, 1)", self.ema_decay));
+        }
+        Ok(())
+    }
+
+    /// The resolution shared by both networks and the dataset.
+    pub fn image_size(&self) -> usize {
+        assert_eq!(
+            self.generator.image_size, self.discriminator.image_size,
+            "Generator and discriminator must be configured for the same image size"
+        );
+        self.generator.image_size
+    }
+}
 
+pub fn train<B: AutodiffBackend>(artifact_dir: &str, dataset_path: &str, config: TrainingConfig, device: B::Device) {
     std::fs::create_dir_all(artifact_dir).ok();
     config
         .save(format!("{artifact_dir}/config.json"))
         .expect("Config should be saved successfully");
 
+    run::<B>(artifact_dir, dataset_path, config, None, device);
+}
+
+/// Continues the run in `artifact_dir` from checkpoint `checkpoint`, or from the latest one.
+pub fn resume<B: AutodiffBackend>(artifact_dir: &str, dataset_path: &str, checkpoint: Option<String>, device: B::Device) {
+    let checkpoint = checkpoint
+        .or_else(|| latest_checkpoint(artifact_dir))
+        .unwrap_or_else(|| panic!("No checkpoint found in {artifact_dir}"));
+    let state = load_state(artifact_dir, &checkpoint);
+    if state.config.num_workers > 1 {
+        println!(
+            "Warning: the run loads batches with {} workers, the rest of epoch {} will not see exactly the batches of the interrupted run.",
+            state.config.num_workers, state.epoch
+        );
+    }
+    println!(
+        "Resuming from checkpoint {checkpoint} (global step {}, learning rates Gen {:.2e} Dis {:.2e}).",
+        state.global_step, state.generator_learning_rate, state.discriminator_learning_rate
+    );
+
+    run::<B>(artifact_dir, dataset_path, state.config.clone(), Some((checkpoint, state)), device);
+}
+
+fn run<B: AutodiffBackend>(artifact_dir: &str, dataset_path: &str, config: TrainingConfig, resume_from: Option<(String, TrainingState)>, device: B::Device) {
+    println!("Starting Training Setup.");
+
     B::seed(config.seed);
 
     let mut generator = config.generator.init::<B>(&burn::nn::Initializer::Normal { mean: 0.0, std: 0.02 });
     let mut discriminator = config.discriminator.init::<B>(&burn::nn::Initializer::Normal { mean: 0.0, std: 0.02 });
-    let mut optimizer_gen = AdamConfig::new().with_beta_1(0.5).init();
-    let mut optimizer_dis = SgdConfig::new().init();
+    let resume_tag = resume_from.as_ref().map(|(checkpoint, _)| checkpoint.as_str());
+    let mut optimizer_gen = config.generator_optimizer.init(resume_tag.map(|tag| optimizer_path(artifact_dir, "gen", tag)));
+    let mut optimizer_dis = config.discriminator_optimizer.init(resume_tag.map(|tag| optimizer_path(artifact_dir, "dis", tag)));
 
-    let image_batcher = ImageBatcher::<B>::new(device.clone());
-    let dataloader = DataLoaderBuilder::new(image_batcher)
-        .batch_size(config.batch_size)
-        .shuffle(config.seed)
-        .num_workers(config.num_workers)
-        .build(make_image_dataset());
+    // Moving average of the generator, used for snapshots and sampling.
+    let mut generator_ema = generator.valid();
 
-    let progress_image_latents = Tensor::<B,1,Float>::ones([config.generator.latent_vector_size]);
+    let mut running_discriminator = RunningDiscriminator::new();
+    let mut augment_probability = config.augment.as_ref().map_or(0.0, |augment| augment.probability);
+    let mut discriminator_steps = 0;
+    let (start_epoch, start_iteration, mut global_step) = match resume_from {
+        Some((checkpoint, state)) => {
+            (generator, discriminator) = load_checkpoint(artifact_dir, &checkpoint, generator, discriminator);
+            generator_ema = load_generator_ema(artifact_dir, &checkpoint, generator_ema).unwrap_or_else(|| {
+                println!("Checkpoint {checkpoint} has no generator average, starting it from the generator.");
+                generator.valid()
+            });
+            running_discriminator = state.running_discriminator;
+            augment_probability = state.augment_probability;
+            discriminator_steps = state.discriminator_steps;
+            (state.epoch, state.iteration + 1, state.global_step)
+        }
+        None => (1, 0, 0),
+    };
+
+    // The schedules are laid out over epochs, so they need the number of batches per epoch.
+    let train_images = make_image_dataset(dataset_path, TRAIN_SPLIT)
+        .unwrap_or_else(|| panic!("Dataset {dataset_path} should contain a \"{TRAIN_SPLIT}\" split"))
+        .len();
+    let steps_per_epoch = if config.drop_last { train_images / config.batch_size } else { train_images.div_ceil(config.batch_size) };
+    if steps_per_epoch == 0 {
+        panic!("Dataset {dataset_path} has {train_images} training images, too few for a single batch of {}", config.batch_size);
+    }
+
+    let has_valid_split = make_image_dataset(dataset_path, VALID_SPLIT).is_some_and(|dataset| !dataset.is_empty());
+    if !has_valid_split {
+        println!("Dataset {dataset_path} has no \"{VALID_SPLIT}\" split, skipping held-out metrics.");
+    }
+
+    let snapshot_latents = config.snapshot.latents::<B::InnerBackend>(artifact_dir, config.generator.latent_vector_size, &device);
 
     println!("Generator Sizes:");
-    generator.forward_print_sizes(progress_image_latents.clone().reshape([1,config.generator.latent_vector_size])).reshape([3,IMAGE_WIDTH, IMAGE_HEIGHT]);
+    generator.forward_print_sizes(Tensor::<B,2,Float>::ones([1, config.generator.latent_vector_size]).to_device(&device));
 
 
     const RING_BUFFER_SIZE: usize = 20;
@@ -59,48 +217,104 @@ pub fn train<B: AutodiffBackend>(artifact_dir: &str, config: TrainingConfig, dev
     let mut num_in_ring_buffer = 0;
     let mut ring_buffer_idx = 0;
 
+    // The metric of the next checkpoint, summed over the steps since the previous one.
+    let (mut metric_sum, mut metric_count) = (0.0, 0);
+    let mut last_checkpoint_time = Instant::now();
+
     println!("Finished Training Setup.");
 
     // Custom Training Loop for GANs
-    for epoch in 1..config.num_epochs + 1{
-        for (iteration, batch) in dataloader.iter().enumerate(){
+    for epoch in start_epoch..config.num_epochs + 1{
+        // Seeding the shuffle by epoch lets a resumed run rebuild the exact batch order of its epoch.
+        // With more than one worker the order of the batches also depends on thread timing.
+        let dataloader = DataLoaderBuilder::new(ImageBatcher::<B>::new(device.clone(), config.image_size(), config.normalization.clone()))
+            .batch_size(config.batch_size)
+            .shuffle(config.seed.wrapping_add(epoch as u64))
+            .num_workers(config.num_workers)
+            .build(make_image_dataset(dataset_path, TRAIN_SPLIT).unwrap_or_else(|| panic!("Dataset {dataset_path} should contain a \"{TRAIN_SPLIT}\" split")));
+        let skipped_iterations = if epoch == start_epoch { start_iteration } else { 0 };
+
+        for (iteration, batch) in dataloader.iter().enumerate().skip(skipped_iterations){
+            // Only the last batch of an epoch can be smaller, every per-sample tensor follows its size.
+            let [batch_size, _, _, _] = batch.images.dims();
+            if config.drop_last && batch_size < config.batch_size {
+                continue;
+            }
             let iter_start_time = Instant::now();
+            B::seed(config.seed.wrapping_add(global_step as u64));
+            let generator_learning_rate = config.generator_optimizer.learning_rate_at(global_step, steps_per_epoch, config.num_epochs);
+            let discriminator_learning_rate = config.discriminator_optimizer.learning_rate_at(global_step, steps_per_epoch, config.num_epochs);
 
-            let noise_for_images = batch.images.random_like(Distribution::Normal(0.0, 0.3));
+            let noise_std = config.instance_noise.std_at(global_step);
+            let noise_for_images = if noise_std > 0.0 { batch.images.random_like(Distribution::Normal(0.0, noise_std)) } else { batch.images.zeros_like() };
+            let (real_target_labels, fake_target_labels) = config.labels.discriminator_targets::<B>(batch_size);
 
             // Update Discriminator Network
-            let mut accumulated_gradients = GradientsAccumulator::<Discriminator<B>>::new();
-            
-            // Generate Loss from Real Images
-            let all_real_discriminator_output = discriminator.forward(batch.images.add(noise_for_images.clone()));
-            let real_target_labes = Tensor::<B,1,Float>::random([config.batch_size], Distribution::Uniform(0.8, 1.0));
-            let generator_loss_0 = cross_entropy_loss(all_real_discriminator_output.clone(), real_target_labes);
-            let grads_real = generator_loss_0.backward();
-            let grads_real = GradientsParams::from_grads(grads_real, &discriminator);
-            accumulated_gradients.accumulate(&discriminator, grads_real);
-
-            // Generate Loss from Fake Images generated by Generator
-            let noise_for_generator: Tensor<B, 2> = Tensor::random([config.batch_size, config.generator.latent_vector_size], Distribution::Normal(0.0, 1.0));
-            let fake_images = generator.forward(noise_for_generator);
-            let all_fake_discriminator_output_1 = discriminator.forward(fake_images.clone().add(noise_for_images).detach());
-            let fake_target_labes = Tensor::<B,1,Float>::random([config.batch_size], Distribution::Uniform(0.0, 0.2));
-            let generator_loss_1 = cross_entropy_loss(all_fake_discriminator_output_1.clone(), fake_target_labes);
-            let grads_fake = generator_loss_1.backward();
-            let grads_fake = GradientsParams::from_grads(grads_fake, &discriminator);
-            accumulated_gradients.accumulate(&discriminator, grads_fake);
-
-            // Apply Loss
-            let total_grads = accumulated_gradients.grads();
-            discriminator = optimizer_dis.step(config.learning_rate, discriminator, total_grads);
-
-            // Update Generator Network
-            // Fake labels are real labels for generator cost: See https://pytorch.org/tutorials/beginner/dcgan_faces_tutorial.html
-            let fake_target_labels = Tensor::<B,1,Float>::random([config.batch_size], Distribution::Uniform(0.8, 1.0));
-            let all_fake_discriminator_output_2 = discriminator.forward(fake_images);
-            let generator_loss_2 = cross_entropy_loss(all_fake_discriminator_output_2.clone(), fake_target_labels);
-            let generator_loss_grads = generator_loss_2.backward();
-            let grads = GradientsParams::from_grads(generator_loss_grads, &generator);
-            generator = optimizer_gen.step(config.learning_rate, generator, grads);
+            // Everything the discriminator sees goes through the same augmentations, when they are enabled.
+            let step_augment_probability = augment_probability;
+            let augment = |images: Tensor<B, 4>| match &config.augment {
+                Some(augment) => augment.augment(images, step_augment_probability),
+                None => images,
+            };
+            let real_images = augment(batch.images.add(noise_for_images.clone()));
+            let real_output = discriminator.forward(real_images.clone());
+
+            let noise_for_generator: Tensor<B, 2> = Tensor::random([batch_size, config.generator.latent_vector_size], Distribution::Normal(0.0, 1.0));
+            let fake_images = config.normalization.generated(generator.forward(noise_for_generator));
+            let fake_input = augment(fake_images.clone().add(noise_for_images).detach());
+            let fake_output_for_discriminator = discriminator.forward(fake_input.clone());
+
+            // Adaptive balancing skips or repeats the discriminator update depending on how far ahead it is.
+            let discriminator_updates = config.balance.as_ref().map_or(1, |balance| balance.discriminator_updates(&running_discriminator));
+            // The lazy penalties follow the discriminator updates, so updates skipped by the balancing do not skip them.
+            let lazy_penalties = discriminator_steps % config.regularization_interval == 0;
+            let (discriminator_loss, penalties) = discriminator_objective(
+                &config,
+                &discriminator,
+                (real_output.clone(), fake_output_for_discriminator.clone()),
+                (real_images.clone(), fake_input.clone()),
+                (real_target_labels.clone(), fake_target_labels.clone()),
+                discriminator_updates > 0,
+                discriminator_updates > 0 && lazy_penalties,
+            );
+            if discriminator_updates > 0 {
+                let grads = GradientsParams::from_grads(discriminator_loss.backward(), &discriminator);
+                discriminator = optimizer_dis.step(discriminator_learning_rate, discriminator, grads);
+                discriminator_steps += 1;
+            }
+            // Repeated updates reuse the batch.
+            for _ in 1..discriminator_updates {
+                let outputs = (discriminator.forward(real_images.clone()), discriminator.forward(fake_input.clone()));
+                let lazy_penalties = discriminator_steps % config.regularization_interval == 0;
+                let (loss, _) = discriminator_objective(&config, &discriminator, outputs, (real_images.clone(), fake_input.clone()), (real_target_labels.clone(), fake_target_labels.clone()), true, lazy_penalties);
+                let grads = GradientsParams::from_grads(loss.backward(), &discriminator);
+                discriminator = optimizer_dis.step(discriminator_learning_rate, discriminator, grads);
+                discriminator_steps += 1;
+            }
+            let real_probability = sigmoid(real_output.clone()).mean().into_scalar().elem::<f64>();
+            let fake_probability = sigmoid(fake_output_for_discriminator).mean().into_scalar().elem::<f64>();
+            if let Some(balance) = &config.balance {
+                running_discriminator.update(real_probability, fake_probability, balance.momentum);
+            }
+            if let Some(ada) = config.augment.as_ref().and_then(|augment| augment.ada.as_ref()) {
+                augment_probability = ada.adjust(augment_probability, real_output.clone());
+            }
+
+            // Update Generator Network, once every `critic_iterations` discriminator updates
+            let generator_report = if (global_step + 1) % config.critic_iterations == 0 {
+                // Fake labels are real labels for generator cost: See https://pytorch.org/tutorials/beginner/dcgan_faces_tutorial.html
+                let generator_target_labels = config.labels.generator_targets::<B>(batch_size);
+                let fake_output_for_generator = discriminator.forward(augment(fake_images));
+                let real_output_for_generator = config.loss.needs_real_logits_for_generator().then(|| discriminator.forward(real_images).detach());
+                let generator_loss = config.loss.generator_loss(fake_output_for_generator.clone(), real_output_for_generator, generator_target_labels);
+                let grads = GradientsParams::from_grads(generator_loss.backward(), &generator);
+                generator = optimizer_gen.step(generator_learning_rate, generator, grads);
+                generator_ema = update_average(generator_ema, &generator, warmup_decay(config.ema_decay, config.ema_warmup_steps, global_step));
+                Some((generator_loss.into_scalar().elem::<f32>(), sigmoid(fake_output_for_generator).mean().into_scalar().elem::<f32>()))
+            } else {
+                None
+            };
+            global_step += 1;
 
 
             let end_iter_time = Instant::now();
@@ -113,51 +327,222 @@ pub fn train<B: AutodiffBackend>(artifact_dir: &str, config: TrainingConfig, dev
             time_ring_buffer[ring_buffer_idx] = end_iter_time - iter_start_time;
             ring_buffer_idx = (ring_buffer_idx + 1) % RING_BUFFER_SIZE;
 
+            let discriminator_loss = discriminator_loss.into_scalar().elem::<f32>();
+
             // Reporting
             if true{
                 let mut total_last_8_time = Duration::from_secs(0);
-                for i in 0..num_in_ring_buffer{
-                    total_last_8_time += time_ring_buffer[i];
+                for iter_time in time_ring_buffer.iter().take(num_in_ring_buffer){
+                    total_last_8_time += *iter_time;
+                }
+                // Batches without a generator update show "-" for its values.
+                let (generator_loss, fake_after_update) = match generator_report {
+                    Some((loss, fake_after_update)) => (format!("{loss:.3}"), format!("{fake_after_update:.3}")),
+                    None => ("-".to_string(), "-".to_string()),
+                };
+                let mut penalties: String = penalties.into_iter().map(|(name, penalty)| format!(" | {name} {:.3}", penalty.into_scalar())).collect();
+                if config.balance.is_some() {
+                    penalties += &format!(" | D updates {discriminator_updates}");
+                }
+                if config.augment.is_some() {
+                    penalties += &format!(" | Aug p {augment_probability:.3}");
                 }
                 println!(
-                    "[{}]: [Train - Epoch {} - Iteration {}] Loss Gen {:.3} | Loss Dis {:.3} | D(x): {:.3} | D(G(z)): {:.3} / {:.3} - Last {RING_BUFFER_SIZE} Iters took: {:.2}s, {:.2}s per Iteration on avg",
+                    "[{}]: [Train - Epoch {} - Iteration {}] Loss Gen {} | Loss Dis {:.3}{} | D(x): {:.3} | D(G(z)): {:.3} / {} | LR Gen {:.2e} Dis {:.2e} - Last {RING_BUFFER_SIZE} Iters took: {:.2}s, {:.2}s per Iteration on avg",
                     Local::now(),
                     epoch,
                     iteration,
-                    generator_loss_2.sum().into_scalar(),
-                    generator_loss_0.add(generator_loss_1).sum().into_scalar(),
-                    all_real_discriminator_output.mean().into_scalar(),
-                    all_fake_discriminator_output_1.mean().into_scalar(),
-                    all_fake_discriminator_output_2.mean().into_scalar(),
+                    generator_loss,
+                    discriminator_loss,
+                    penalties,
+                    // Reported as probabilities whatever the loss, so runs with different losses compare.
+                    real_probability,
+                    fake_probability,
+                    fake_after_update,
+                    generator_learning_rate,
+                    discriminator_learning_rate,
                     total_last_8_time.as_secs_f32(),
                     total_last_8_time.as_secs_f32() / num_in_ring_buffer as f32,
                 );
             }
-            if true{
-                let image_generated = generator.forward(progress_image_latents.clone().reshape([1,config.generator.latent_vector_size])).reshape([3,IMAGE_WIDTH, IMAGE_HEIGHT]);
-                tensor_to_image(&format!("gan_progress_output/{epoch}-{iteration}-progress.png"), image_generated);
+            if config.snapshot.interval > 0 && global_step % config.snapshot.interval == 0 {
+                config.snapshot.write(artifact_dir, &generator_ema, snapshot_latents.clone(), &config.normalization, global_step);
             }
 
-            if iteration % 100 == 0{
-                generator
-                    .clone()
-                    .save_file(format!("{artifact_dir}/generator-{epoch}-{iteration}"), &CompactRecorder::new())
-                    .expect("Generator model should be saved successfully");
-                discriminator
-                    .clone()
-                    .save_file(format!("{artifact_dir}/discriminator-{epoch}-{iteration}"), &CompactRecorder::new())
-                    .expect("Discriminator model should be saved successfully");
-                println!("[{}]: Successfully Saved Models", Local::now());
+            if let Some(metric) = config.checkpoint.metric.of_step(generator_report.map(|(loss, _)| loss), discriminator_loss, real_probability, fake_probability) {
+                metric_sum += metric;
+                metric_count += 1;
+            }
+            // The last step of the run is always saved, the steps since the previous checkpoint would be lost otherwise.
+            let last_step = epoch == config.num_epochs && iteration + 1 == steps_per_epoch;
+            if last_step || config.checkpoint.is_due(global_step, last_checkpoint_time.elapsed()) {
+                let state = TrainingState::new(config.clone(), epoch, iteration, global_step, generator_learning_rate, discriminator_learning_rate)
+                    .with_running_discriminator(running_discriminator.clone())
+                    .with_augment_probability(augment_probability)
+                    .with_discriminator_steps(discriminator_steps);
+                save_checkpoint(artifact_dir, &state, &generator, &generator_ema, &discriminator, optimizer_gen.as_ref(), optimizer_dis.as_ref());
+                let tag = checkpoint_tag(global_step);
+                config.checkpoint.retain(artifact_dir, &tag, global_step, (metric_count > 0).then(|| metric_sum / metric_count as f64));
+                (metric_sum, metric_count) = (0.0, 0);
+                last_checkpoint_time = Instant::now();
+                println!("[{}]: Successfully Saved Checkpoint {}", Local::now(), tag);
             }
         }
+
+        if has_valid_split {
+            let valid_dataset = make_image_dataset(dataset_path, VALID_SPLIT).expect("Validation split should still exist");
+            let (real_accuracy, fake_accuracy) = held_out_accuracy(&generator.valid(), &discriminator.valid(), valid_dataset, &config, epoch, &device);
+            println!(
+                "[{}]: [Valid - Epoch {}] Discriminator accuracy on held-out real images {:.3} | on as many generated images {:.3}",
+                Local::now(),
+                epoch,
+                real_accuracy,
+                fake_accuracy,
+            );
+        }
+    }
+
+    if config.snapshot.timelapse {
+        config.snapshot.write_timelapse(artifact_dir);
+    }
+}
+
+/// Loss of the discriminator on one batch, with its input gradient penalties.
+///
+/// Returns the penalties without their weights alongside for the report.
+fn discriminator_objective<B: AutodiffBackend>(
+    config: &TrainingConfig,
+    discriminator: &Discriminator<B>,
+    (real_output, fake_output): (Tensor<B, 1>, Tensor<B, 1>),
+    (real_images, fake_images): (Tensor<B, 4>, Tensor<B, 4>),
+    (real_targets, fake_targets): (Tensor<B, 1>, Tensor<B, 1>),
+    gradient_penalty_enabled: bool,
+    lazy_penalties: bool,
+) -> (Tensor<B, 1>, Vec<(&'static str, Tensor<B, 1>)>) {
+    let mut loss = config.loss.discriminator_loss(real_output, fake_output, real_targets, fake_targets);
+    let mut penalties = Vec::new();
+    if gradient_penalty_enabled && config.gradient_penalty_weight > 0.0 {
+        let penalty = gradient_penalty(discriminator, real_images.clone(), fake_images.clone());
+        loss = loss + penalty.clone().mul_scalar(config.gradient_penalty_weight);
+        penalties.push(("GP", penalty));
+    }
+    // Lazy regularization: R1 and R2 only run every `regularization_interval` discriminator updates and are scaled up to keep their strength.
+    if lazy_penalties {
+        let lazy_weight = 0.5 * config.regularization_interval as f64;
+        if config.r1_weight > 0.0 {
+            let penalty = zero_centered_gradient_penalty(discriminator, real_images);
+            loss = loss + penalty.clone().mul_scalar(config.r1_weight * lazy_weight);
+            penalties.push(("R1", penalty));
+        }
+        if config.r2_weight > 0.0 {
+            let penalty = zero_centered_gradient_penalty(discriminator, fake_images);
+            loss = loss + penalty.clone().mul_scalar(config.r2_weight * lazy_weight);
+            penalties.push(("R2", penalty));
+        }
+    }
+    (loss, penalties)
+}
+
+/// Fraction of the held-out real images the discriminator judges real, and of as many generated images it judges fake.
+///
+/// Runs without autodiff so batch norm and dropout are in inference mode.
+fn held_out_accuracy<B: Backend>(generator: &Generator<B>, discriminator: &Discriminator<B>, dataset: SqliteDataset<DataSerialize<u8>>, config: &TrainingConfig, epoch: usize, device: &B::Device) -> (f32, f32) {
+    let count = dataset.len() as f32;
+    let dataloader = DataLoaderBuilder::new(ImageBatcher::<B>::new(device.clone(), config.image_size(), config.normalization.clone()))
+        .batch_size(config.batch_size)
+        .num_workers(config.num_workers)
+        .build(dataset);
+
+    B::seed(config.seed.wrapping_add(epoch as u64));
+    let (mut real_correct, mut fake_correct) = (0.0, 0.0);
+    for batch in dataloader.iter() {
+        let [batch_size, _, _, _] = batch.images.dims();
+        let real_output = discriminator.forward(batch.images);
+        real_correct += real_output.greater_elem(0.0).float().sum().into_scalar().elem::<f32>();
+
+        let latents = Tensor::<B, 2>::random([batch_size, config.generator.latent_vector_size], Distribution::Normal(0.0, 1.0)).to_device(device);
+        let fake_output = discriminator.forward(config.normalization.generated(generator.forward(latents)));
+        fake_correct += fake_output.lower_equal_elem(0.0).float().sum().into_scalar().elem::<f32>();
     }
+    (real_correct / count, fake_correct / count)
 }
 
-pub fn tensor_to_image<B: Backend>(path: &str, tensor: Tensor<B, 3>){
-    let data: Data<f32, 3> = tensor.into_data().convert();
-        
-    let image_data: Vec<u8> = data.value.iter().map(|pix_chan| ((pix_chan + 0.5) * 255.0) as u8).collect();
+#[cfg(test)]
+mod tests {
+    use burn::{backend::{Autodiff, NdArray}, data::dataset::SqliteDatasetWriter, tensor::{Data, DataSerialize, Shape}};
+
+    use super::*;
+
+    type TestBackend = Autodiff<NdArray<f32>>;
+
+    fn config() -> TrainingConfig {
+        TrainingConfig::new(GeneratorConfig::new(), DiscriminatorConfig::new())
+    }
+
+    /// Small networks for runs on a dataset of 32x32 images.
+    fn tiny_config(batch_size: usize, num_epochs: usize, drop_last: bool) -> TrainingConfig {
+        TrainingConfig::new(
+            GeneratorConfig::new().with_latent_vector_size(4).with_feature_map_size(2).with_image_size(32),
+            DiscriminatorConfig::new().with_feature_map_size(2).with_image_size(32),
+        )
+        .with_batch_size(batch_size)
+        .with_num_epochs(num_epochs)
+        .with_drop_last(drop_last)
+        .with_num_workers(1)
+    }
+
+    /// Trains on a baked dataset of `images` gray 32x32 images and returns the global step of the final checkpoint.
+    fn trained_steps(name: &str, images: usize, config: TrainingConfig) -> usize {
+        let dir = std::env::temp_dir().join(format!("gamma-train-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_file = dir.join("images.sqlite").to_str().unwrap().to_string();
+        let mut writer: SqliteDatasetWriter<DataSerialize<u8>> = SqliteDatasetWriter::new(&db_file, false).unwrap();
+        for _ in 0..images {
+            writer.write(TRAIN_SPLIT, &Data::new(vec![128; 32 * 32 * 3], Shape::new([32, 32, 3])).serialize()).unwrap();
+        }
+        writer.set_completed().unwrap();
+
+        let artifact_dir = dir.join("artifacts").to_str().unwrap().to_string();
+        train::<TestBackend>(&artifact_dir, &db_file, config, Default::default());
+        let state = load_state(&artifact_dir, &latest_checkpoint(&artifact_dir).expect("The last step should be checkpointed"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        state.global_step
+    }
+
+    #[test]
+    fn batch_smaller_than_batch_size_trains_one_step() {
+        assert_eq!(trained_steps("small-batch", 5, tiny_config(8, 1, false)), 1);
+    }
+
+    #[test]
+    fn drop_last_skips_the_short_batch_of_every_epoch() {
+        assert_eq!(trained_steps("keep-last", 5, tiny_config(2, 2, false)), 6);
+        assert_eq!(trained_steps("drop-last", 5, tiny_config(2, 2, true)), 4);
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(config().validate(), Ok(()));
+    }
 
-    let new_image: image::ImageBuffer<Rgb<u8>, Vec<u8>> = image::ImageBuffer::from_raw(IMAGE_WIDTH as u32, IMAGE_HEIGHT as u32, image_data).unwrap();
-    new_image.save(path).unwrap();
-}
\ No newline at end of file
+    #[test]
+    fn validate_rejects_different_image_sizes() {
+        let mut config = config();
+        config.discriminator.image_size = 32;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_gradient_penalties_with_batch_norm() {
+        for penalty in [TrainingConfig::with_gradient_penalty_weight, TrainingConfig::with_r1_weight, TrainingConfig::with_r2_weight] {
+            let config = penalty(config(), 10.0);
+            assert!(config.validate().is_err());
+            let mut config = config;
+            config.discriminator.norm = Norm::Spectral;
+            assert_eq!(config.validate(), Ok(()));
+            config.discriminator.norm = Norm::None;
+            assert_eq!(config.validate(), Ok(()));
+        }
+    }
+}